@@ -2,6 +2,8 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
+// The glob below also brings the `Error`s of sqrid and andex
+#[allow(hidden_glob_reexports)]
 use std::error::Error;
 
 extern crate the_fall;
@@ -0,0 +1,15 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+// The glob below also brings the `Error`s of sqrid and andex
+#[allow(hidden_glob_reexports)]
+use std::error::Error;
+
+extern crate the_fall;
+pub use self::the_fall::*;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    entrypoint3::main()?;
+    Ok(())
+}
@@ -4,21 +4,179 @@
 
 use std::collections::VecDeque;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops;
 
 use super::andex::*;
+use super::sqrid;
 
 pub const MAX_WIDTH: u16 = 20;
 pub const MAX_HEIGHT: u16 = 20;
+pub const MAX_ROCKS: usize = 10;
 
-pub type Sqrid = crate::sqrid_create!(MAX_WIDTH, MAX_HEIGHT, false);
-pub type Qa = crate::qa_create!(Sqrid);
 pub type Qr = crate::Qr;
-pub type Gridbool = crate::gridbool_create!(Sqrid);
+
+/* Dims *************************************************************/
+
+/// The sqrid position operations used by the generic code
+pub trait QaOps:
+    Copy
+    + Default
+    + fmt::Debug
+    + fmt::Display
+    + Eq
+    + Hash
+    + Ord
+    + Into<usize>
+    + TryFrom<(u16, u16), Error = sqrid::Error>
+    + ops::Add<Qr, Output = Option<Self>>
+{
+    const WIDTH: u16;
+    const HEIGHT: u16;
+    const SIZE: usize;
+    fn tuple(&self) -> (u16, u16);
+    fn iter() -> impl Iterator<Item = Self>;
+}
+
+impl<const W: u16, const H: u16> QaOps for sqrid::Qa<W, H> {
+    const WIDTH: u16 = W;
+    const HEIGHT: u16 = H;
+    const SIZE: usize = W as usize * H as usize;
+    fn tuple(&self) -> (u16, u16) {
+        sqrid::Qa::tuple(self)
+    }
+    fn iter() -> impl Iterator<Item = Self> {
+        sqrid::Qa::iter()
+    }
+}
+
+/// The sqrid bool-grid operations used by the generic code
+pub trait GridboolOps<Q>:
+    Copy + Default + fmt::Debug + ops::Index<Q, Output = bool> + FromIterator<Q>
+{
+    fn set_t(&mut self, qa: Q);
+    fn iter_t(&self) -> impl Iterator<Item = Q> + '_;
+}
+
+impl<const W: u16, const H: u16, const WORDS: usize> GridboolOps<sqrid::Qa<W, H>>
+    for sqrid::Gridbool<W, H, WORDS>
+{
+    fn set_t(&mut self, qa: sqrid::Qa<W, H>) {
+        sqrid::Gridbool::set_t(self, qa)
+    }
+    fn iter_t(&self) -> impl Iterator<Item = sqrid::Qa<W, H>> + '_ {
+        sqrid::Gridbool::iter_t(self)
+    }
+}
+
+/// The andex operations used by the generic code
+pub trait IRockOps:
+    Copy
+    + fmt::Debug
+    + fmt::Display
+    + Eq
+    + Ord
+    + Into<usize>
+    + TryFrom<usize, Error = crate::andex::Error>
+{
+    const SIZE: usize;
+    fn iter() -> impl Iterator<Item = Self>;
+}
+
+impl<M, const N: usize> IRockOps for Andex<M, N> {
+    const SIZE: usize = N;
+    fn iter() -> impl Iterator<Item = Self> {
+        Andex::iter()
+    }
+}
+
+/// What can be stored in a [`RockArrayOps`]
+pub trait RockItem: Copy + Default + fmt::Debug + Eq {}
+
+impl<T: Copy + Default + fmt::Debug + Eq> RockItem for T {}
+
+/// The andexable array operations used by the generic code
+pub trait RockArrayOps<I, T>:
+    Copy + Default + fmt::Debug + ops::Index<I, Output = T> + ops::IndexMut<I>
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a;
+}
+
+impl<M, T: RockItem, const N: usize> RockArrayOps<Andex<M, N>, T>
+    for AndexableArray<Andex<M, N>, T, N>
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        AndexableArray::iter(self)
+    }
+}
+
+/// Grid dimensions: the types that hold positions and grids of a
+/// given maximum size, and the rocks that can be in it at once
+///
+/// Create new ones with [`dims_create`](crate::dims_create).
+pub trait Dims: Copy + Default + fmt::Debug + Eq + Hash + 'static {
+    type Qa: QaOps;
+    type Grid: Copy
+        + Default
+        + fmt::Debug
+        + Eq
+        + ops::Index<Self::Qa, Output = Cell>
+        + ops::IndexMut<Self::Qa>;
+    type Gridbool: GridboolOps<Self::Qa>;
+    type IRock: IRockOps;
+    /// Array with one slot per rock; a rock keeps its slot while it
+    /// lives
+    type RockArray<T: RockItem>: RockArrayOps<Self::IRock, T>;
+}
+
+/// Creates a [`Dims`](crate::core::Dims) marker type for grids of up
+/// to `$width` x `$height` rooms, with up to `$rocks` rocks at once
+/// ([`MAX_ROCKS`](crate::core::MAX_ROCKS) if omitted)
+#[macro_export]
+macro_rules! dims_create {
+    ($name: ident, $width: expr, $height: expr) => {
+        $crate::dims_create!($name, $width, $height, $crate::core::MAX_ROCKS);
+    };
+    ($name: ident, $width: expr, $height: expr, $rocks: expr) => {
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $name;
+        impl $crate::core::Dims for $name {
+            type Qa = $crate::Qa<{ $width }, { $height }>;
+            type Grid = $crate::Grid<
+                $crate::core::Cell,
+                { $width },
+                { $height },
+                { $width as usize * $height as usize },
+            >;
+            type Gridbool = $crate::Gridbool<
+                { $width },
+                { $height },
+                { ($width as usize * $height as usize).div_ceil(32) },
+            >;
+            type IRock = $crate::Andex<$crate::core::IRockMarker, { $rocks }>;
+            type RockArray<T: $crate::core::RockItem> =
+                $crate::AndexableArray<Self::IRock, T, { $rocks }>;
+        }
+    };
+}
+
+crate::dims_create!(Dims20, MAX_WIDTH, MAX_HEIGHT);
+
+pub type Qa = <Dims20 as Dims>::Qa;
+pub type Gridbool = <Dims20 as Dims>::Gridbool;
+pub type Grid = <Dims20 as Dims>::Grid;
+pub type IRock = <Dims20 as Dims>::IRock;
 
 /* Cell *************************************************************/
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub enum Cell {
+    #[default]
     Type0,
     Type1,
     Type2,
@@ -35,12 +193,6 @@ pub enum Cell {
     Type13,
 }
 
-impl Default for Cell {
-    fn default() -> Cell {
-        Cell::Type0
-    }
-}
-
 impl From<Cell> for char {
     fn from(cell: Cell) -> char {
         match cell {
@@ -87,109 +239,139 @@ impl fmt::Display for Cell {
     }
 }
 
+/// Directions of movement through which entities enter a room: from
+/// the top, from the left and from the right
+pub const ENTRY_DIRS: [Qr; 3] = [Qr::S, Qr::E, Qr::W];
+
+/// Connectivity of a cell type
+#[derive(Debug)]
+pub struct CellInfo {
+    /// Exit direction for each of the `ENTRY_DIRS`
+    pub exits: [Option<Qr>; 3],
+    /// The types that the cell can be rotated into, clockwise
+    pub group: &'static [Cell],
+}
+
+const GROUP_2: &[Cell] = &[Cell::Type2, Cell::Type3];
+const GROUP_4: &[Cell] = &[Cell::Type4, Cell::Type5];
+const GROUP_6: &[Cell] = &[Cell::Type6, Cell::Type7, Cell::Type8, Cell::Type9];
+const GROUP_10: &[Cell] = &[Cell::Type10, Cell::Type11, Cell::Type12, Cell::Type13];
+
+/// `CellInfo` of each cell type, indexed by the type number
+const CELL_INFO: [CellInfo; 14] = [
+    CellInfo {
+        exits: [None, None, None],
+        group: &[Cell::Type0],
+    },
+    CellInfo {
+        exits: [Some(Qr::S), Some(Qr::S), Some(Qr::S)],
+        group: &[Cell::Type1],
+    },
+    CellInfo {
+        exits: [None, Some(Qr::E), Some(Qr::W)],
+        group: GROUP_2,
+    },
+    CellInfo {
+        exits: [Some(Qr::S), None, None],
+        group: GROUP_2,
+    },
+    CellInfo {
+        exits: [Some(Qr::W), None, Some(Qr::S)],
+        group: GROUP_4,
+    },
+    CellInfo {
+        exits: [Some(Qr::E), Some(Qr::S), None],
+        group: GROUP_4,
+    },
+    CellInfo {
+        exits: [None, Some(Qr::E), Some(Qr::W)],
+        group: GROUP_6,
+    },
+    CellInfo {
+        exits: [Some(Qr::S), None, Some(Qr::S)],
+        group: GROUP_6,
+    },
+    CellInfo {
+        exits: [None, Some(Qr::S), Some(Qr::S)],
+        group: GROUP_6,
+    },
+    CellInfo {
+        exits: [Some(Qr::S), Some(Qr::S), None],
+        group: GROUP_6,
+    },
+    CellInfo {
+        exits: [Some(Qr::W), None, None],
+        group: GROUP_10,
+    },
+    CellInfo {
+        exits: [Some(Qr::E), None, None],
+        group: GROUP_10,
+    },
+    CellInfo {
+        exits: [None, None, Some(Qr::S)],
+        group: GROUP_10,
+    },
+    CellInfo {
+        exits: [None, Some(Qr::S), None],
+        group: GROUP_10,
+    },
+];
+
 impl Cell {
+    pub const ALL: [Cell; 14] = [
+        Cell::Type0,
+        Cell::Type1,
+        Cell::Type2,
+        Cell::Type3,
+        Cell::Type4,
+        Cell::Type5,
+        Cell::Type6,
+        Cell::Type7,
+        Cell::Type8,
+        Cell::Type9,
+        Cell::Type10,
+        Cell::Type11,
+        Cell::Type12,
+        Cell::Type13,
+    ];
+
+    pub fn info(&self) -> &'static CellInfo {
+        &CELL_INFO[*self as usize]
+    }
+
+    /// Direction in which an entity that enters the room moving in
+    /// direction `dir` leaves it
     pub fn enter(&self, dir: &Qr) -> Option<Qr> {
-        let dir = *dir;
-        if dir == Qr::N {
-            return None;
-        }
-        match self {
-            Cell::Type0 => None,
-            Cell::Type1 => Some(Qr::S),
-            Cell::Type2 => match dir {
-                Qr::E => Some(Qr::E),
-                Qr::W => Some(Qr::W),
-                _ => None,
-            },
-            Cell::Type3 => match dir {
-                Qr::S => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type4 => match dir {
-                Qr::S => Some(Qr::W),
-                Qr::W => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type5 => match dir {
-                Qr::S => Some(Qr::E),
-                Qr::E => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type6 => match dir {
-                Qr::W => Some(Qr::W),
-                Qr::E => Some(Qr::E),
-                _ => None,
-            },
-            Cell::Type7 => match dir {
-                Qr::S => Some(Qr::S),
-                Qr::W => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type8 => match dir {
-                Qr::W => Some(Qr::S),
-                Qr::E => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type9 => match dir {
-                Qr::S => Some(Qr::S),
-                Qr::E => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type10 => match dir {
-                Qr::S => Some(Qr::W),
-                _ => None,
-            },
-            Cell::Type11 => match dir {
-                Qr::S => Some(Qr::E),
-                _ => None,
-            },
-            Cell::Type12 => match dir {
-                Qr::W => Some(Qr::S),
-                _ => None,
-            },
-            Cell::Type13 => match dir {
-                Qr::E => Some(Qr::S),
-                _ => None,
-            },
-        }
+        let i = ENTRY_DIRS.iter().position(|d| d == dir)?;
+        self.info().exits[i]
+    }
+
+    /// Directions of movement that enter the room and leave it
+    /// moving in direction `exit`
+    pub fn entries_for_exit(&self, exit: &Qr) -> impl Iterator<Item = Qr> + 'static {
+        let exit = *exit;
+        let exits = self.info().exits;
+        ENTRY_DIRS
+            .into_iter()
+            .zip(exits)
+            .filter(move |(_, e)| *e == Some(exit))
+            .map(|(d, _)| d)
+    }
+
+    /// Position of the cell in its rotation group
+    fn group_index(&self) -> usize {
+        let group = self.info().group;
+        group.iter().position(|c| c == self).unwrap()
     }
 
     pub fn rot_cw(&self) -> Cell {
-        match self {
-            Cell::Type0 => Cell::Type0,
-            Cell::Type1 => Cell::Type1,
-            Cell::Type2 => Cell::Type3,
-            Cell::Type3 => Cell::Type2,
-            Cell::Type4 => Cell::Type5,
-            Cell::Type5 => Cell::Type4,
-            Cell::Type6 => Cell::Type7,
-            Cell::Type7 => Cell::Type8,
-            Cell::Type8 => Cell::Type9,
-            Cell::Type9 => Cell::Type6,
-            Cell::Type10 => Cell::Type11,
-            Cell::Type11 => Cell::Type12,
-            Cell::Type12 => Cell::Type13,
-            Cell::Type13 => Cell::Type10,
-        }
+        let group = self.info().group;
+        group[(self.group_index() + 1) % group.len()]
     }
 
     pub fn rot_cc(&self) -> Cell {
-        match self {
-            Cell::Type0 => Cell::Type0,
-            Cell::Type1 => Cell::Type1,
-            Cell::Type2 => Cell::Type3,
-            Cell::Type3 => Cell::Type2,
-            Cell::Type4 => Cell::Type5,
-            Cell::Type5 => Cell::Type4,
-            Cell::Type6 => Cell::Type9,
-            Cell::Type7 => Cell::Type6,
-            Cell::Type8 => Cell::Type7,
-            Cell::Type9 => Cell::Type8,
-            Cell::Type10 => Cell::Type13,
-            Cell::Type11 => Cell::Type10,
-            Cell::Type12 => Cell::Type11,
-            Cell::Type13 => Cell::Type12,
-        }
+        let group = self.info().group;
+        group[(self.group_index() + group.len() - 1) % group.len()]
     }
 
     pub fn rotate(&self, rot: &Rotation) -> Cell {
@@ -199,29 +381,46 @@ impl Cell {
         }
     }
 
+    /// The cell after `n` turns in the given direction
+    pub fn rotate_n(&self, rot: &Rotation, n: usize) -> Cell {
+        let group = self.info().group;
+        let n = n % group.len();
+        let i = match rot {
+            Rotation::Left => self.group_index() + group.len() - n,
+            Rotation::Right => self.group_index() + n,
+        };
+        group[i % group.len()]
+    }
+
+    /// All orientations of the cell, clockwise, starting with itself
+    pub fn orientations(&self) -> impl Iterator<Item = Cell> + 'static {
+        let cell = *self;
+        (0..self.info().group.len()).map(move |n| cell.rotate_n(&Rotation::Right, n))
+    }
+
+    /// Shortest sequence of rotations that turns the cell into
+    /// `target`, or `None` if `target` is not one of its
+    /// orientations; ties go to `Rotation::Left`.
+    pub fn rotations_to(&self, target: &Cell) -> Option<Vec<Rotation>> {
+        let len = self.info().group.len();
+        let right = self.orientations().position(|c| c == *target)?;
+        let left = (len - right) % len;
+        Some(if right < left {
+            vec![Rotation::Right; right]
+        } else {
+            vec![Rotation::Left; left]
+        })
+    }
+
+    /// Number of distinct orientations the cell can be rotated into
     pub fn num_rot(&self) -> usize {
-        match self {
-            Cell::Type0 => 0,
-            Cell::Type1 => 0,
-            Cell::Type2 => 1,
-            Cell::Type3 => 1,
-            Cell::Type4 => 1,
-            Cell::Type5 => 1,
-            Cell::Type6 => 3,
-            Cell::Type7 => 3,
-            Cell::Type8 => 3,
-            Cell::Type9 => 3,
-            Cell::Type10 => 3,
-            Cell::Type11 => 3,
-            Cell::Type12 => 3,
-            Cell::Type13 => 3,
-        }
+        self.info().group.len() - 1
     }
 }
 
 /* Rotation *********************************************************/
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Rotation {
     Left,
     Right,
@@ -238,14 +437,14 @@ impl fmt::Display for Rotation {
 
 /* Entity ***********************************************************/
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct Entity {
-    pub qa: Qa,
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Entity<D: Dims = Dims20> {
+    pub qa: D::Qa,
     pub qr: Qr,
 }
 
-impl Entity {
-    pub fn step(&self, node: &Node) -> Option<Entity> {
+impl<D: Dims> Entity<D> {
+    pub fn step(&self, node: &Node<D>) -> Option<Entity<D>> {
         let cell = node.grid[self.qa];
         if let Some(qr) = cell.enter(&self.qr) {
             if let Some(qa) = self.qa + qr {
@@ -254,7 +453,7 @@ impl Entity {
         }
         None
     }
-    pub fn iter<'a>(&self, node: &'a Node) -> EntityPathIter<'a> {
+    pub fn iter<'a>(&self, node: &'a Node<D>) -> EntityPathIter<'a, D> {
         EntityPathIter {
             entity: Some(*self),
             node,
@@ -262,26 +461,26 @@ impl Entity {
     }
 }
 
-impl fmt::Display for Entity {
+impl<D: Dims> fmt::Display for Entity<D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let t = self.qa.tuple();
         write!(f, "({} {}, {})", t.0, t.1, self.qr)
     }
 }
 
-impl AsRef<Qa> for Entity {
-    fn as_ref(&self) -> &Qa {
+impl<D: Dims> AsRef<D::Qa> for Entity<D> {
+    fn as_ref(&self) -> &D::Qa {
         &self.qa
     }
 }
 
-pub struct EntityPathIter<'a> {
-    pub entity: Option<Entity>,
-    pub node: &'a Node,
+pub struct EntityPathIter<'a, D: Dims = Dims20> {
+    pub entity: Option<Entity<D>>,
+    pub node: &'a Node<D>,
 }
 
-impl<'a> Iterator for EntityPathIter<'a> {
-    type Item = Entity;
+impl<'a, D: Dims> Iterator for EntityPathIter<'a, D> {
+    type Item = Entity<D>;
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(ent0) = self.entity {
             self.entity = ent0.step(self.node);
@@ -292,18 +491,18 @@ impl<'a> Iterator for EntityPathIter<'a> {
 
 /* Action ***********************************************************/
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Action {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action<D: Dims = Dims20> {
     Wait,
-    Rotate { qa: Qa, rot: Rotation },
+    Rotate { qa: D::Qa, rot: Rotation },
 }
 
-impl Action {
-    pub fn new(qa: Qa, rot: Rotation) -> Action {
+impl<D: Dims> Action<D> {
+    pub fn new(qa: D::Qa, rot: Rotation) -> Action<D> {
         Action::Rotate { qa, rot }
     }
 
-    pub fn qa(&self) -> Option<Qa> {
+    pub fn qa(&self) -> Option<D::Qa> {
         match self {
             Action::Rotate { qa, rot: _ } => Some(*qa),
             _ => None,
@@ -317,26 +516,80 @@ impl Action {
         }
     }
 
-    pub fn available(params: &Params, node: &Node, focus: &Entity) -> Vec<Vec<Action>> {
-        let mut ret = vec![vec![]];
-        if !params.frozen[focus.qa] {
-            let num_rot = node.grid[focus.qa].num_rot();
-            if num_rot > 0 {
-                ret.push(vec![Action::new(focus.qa, Rotation::Left)]);
-            }
-            if num_rot > 1 {
-                ret.push(vec![Action::new(focus.qa, Rotation::Right)]);
-                ret.push(vec![
-                    Action::new(focus.qa, Rotation::Right),
-                    Action::new(focus.qa, Rotation::Right),
-                ]);
-            }
+    /// Checks the rules that the game enforces before applying an action
+    pub fn check(&self, params: &Params<D>, node: &Node<D>) -> Result<(), IllegalAction<D>> {
+        let qa = match self {
+            Action::Wait => return Ok(()),
+            Action::Rotate { qa, rot: _ } => *qa,
+        };
+        let t = qa.tuple();
+        if t.0 >= params.width || t.1 >= params.height {
+            return Err(IllegalAction::OutOfBounds(qa));
         }
+        if params.frozen[qa] {
+            return Err(IllegalAction::Frozen(qa));
+        }
+        if node.grid[qa].num_rot() == 0 {
+            return Err(IllegalAction::NotRotatable(qa, node.grid[qa]));
+        }
+        if node.indy.qa == qa {
+            return Err(IllegalAction::Indy(qa));
+        }
+        if let Some(irock) =
+            D::IRock::iter().find(|irock| node.rock[*irock].map(|r| r.qa) == Some(qa))
+        {
+            return Err(IllegalAction::Rock(qa, irock));
+        }
+        Ok(())
+    }
+
+    pub fn is_valid(&self, params: &Params<D>, node: &Node<D>) -> bool {
+        self.check(params, node).is_ok()
+    }
+
+    /// Rotation sequences that can be applied to the room at `qa`,
+    /// shortest first; each action of a sequence takes its own turn.
+    pub fn available(params: &Params<D>, node: &Node<D>, qa: &D::Qa) -> Vec<Vec<Action<D>>> {
+        let qa = *qa;
+        if params.frozen[qa] {
+            return vec![vec![]];
+        }
+        let cell = node.grid[qa];
+        let mut ret = cell
+            .orientations()
+            .filter_map(|target| cell.rotations_to(&target))
+            .map(|rots| rots.into_iter().map(|rot| Action::new(qa, rot)).collect())
+            .collect::<Vec<Vec<Action<D>>>>();
+        ret.sort_by_key(Vec::len);
         ret
     }
 }
 
-impl fmt::Display for Action {
+/// Game rule broken by an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalAction<D: Dims = Dims20> {
+    OutOfBounds(D::Qa),
+    Frozen(D::Qa),
+    NotRotatable(D::Qa, Cell),
+    Indy(D::Qa),
+    Rock(D::Qa, D::IRock),
+}
+
+impl<D: Dims> fmt::Display for IllegalAction<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IllegalAction::OutOfBounds(qa) => write!(f, "room {} is out of bounds", qa),
+            IllegalAction::Frozen(qa) => write!(f, "room {} is frozen", qa),
+            IllegalAction::NotRotatable(qa, cell) => {
+                write!(f, "room {} of type {} can't rotate", qa, char::from(*cell))
+            }
+            IllegalAction::Indy(qa) => write!(f, "room {} has indy", qa),
+            IllegalAction::Rock(qa, irock) => write!(f, "room {} has rock {}", qa, irock),
+        }
+    }
+}
+
+impl<D: Dims> fmt::Display for Action<D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Action::Wait => write!(f, "WAIT"),
@@ -351,33 +604,130 @@ impl fmt::Display for Action {
 /* Rocks: Andex *****************************************************/
 
 pub enum IRockMarker {}
-pub type IRock = Andex<IRockMarker, 10>;
-pub type Rocks = AndexableArray<IRock, Option<Entity>, { IRock::SIZE }>;
+pub type Rocks<D = Dims20> = <D as Dims>::RockArray<Option<Entity<D>>>;
+
+/// How a rock got destroyed
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum RockEnd<D: Dims = Dims20> {
+    /// Left the grid or entered a room through a wall
+    Wall(D::Qa),
+    /// Met the other rock in the same room
+    Crash(D::IRock, D::Qa),
+}
+
+pub type RockEnds<D = Dims20> = <D as Dims>::RockArray<Option<RockEnd<D>>>;
+
+/// Where new rocks can show up: rooms on the top, left and right
+/// edges that accept an entry from outside the grid
+pub fn rock_spawns<D: Dims>(params: &Params<D>, node: &Node<D>) -> Vec<Entity<D>> {
+    let mut spawns = vec![];
+    for qa in D::Qa::iter() {
+        let t = qa.tuple();
+        if t.0 >= params.width || t.1 >= params.height || qa == node.indy.qa {
+            continue;
+        }
+        let dirs = [
+            (t.1 == 0, Qr::S),
+            (t.0 == 0, Qr::E),
+            (t.0 == params.width - 1, Qr::W),
+        ];
+        for (edge, qr) in dirs {
+            if edge && node.grid[qa].enter(&qr).is_some() {
+                spawns.push(Entity { qa, qr });
+            }
+        }
+    }
+    spawns
+}
+
+/* Zobrist hashing **************************************************/
+
+/// Zobrist key of the cell in the room; empty rooms have key 0, so
+/// that the default grid hashes to 0.
+pub fn zobrist_key(qa: impl Into<usize>, cell: Cell) -> u64 {
+    if cell == Cell::Type0 {
+        return 0;
+    }
+    // splitmix64 of the (room, cell) index
+    let mut z = (qa.into() * 14 + cell as usize) as u64;
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Zobrist hash of the whole grid, computed from scratch
+pub fn grid_zobrist<D: Dims>(grid: &D::Grid) -> u64 {
+    D::Qa::iter().fold(0, |z, qa| z ^ zobrist_key(qa, grid[qa]))
+}
 
 /* Params, Node *****************************************************/
 
 #[derive(Debug, Default)]
-pub struct Params {
+pub struct Params<D: Dims = Dims20> {
     pub width: u16,
     pub height: u16,
-    pub exit: Qa,
-    pub frozen: Gridbool,
-    pub grid0: crate::grid_create!(Sqrid, Cell),
+    pub exit: D::Qa,
+    pub frozen: D::Gridbool,
+    pub grid0: D::Grid,
 }
 
 #[derive(Debug, Default, Clone, Copy)]
-pub struct Node {
-    pub grid: crate::grid_create!(Sqrid, Cell),
-    pub indy: Entity,
-    pub rock: Rocks,
+pub struct Node<D: Dims = Dims20> {
+    pub grid: D::Grid,
+    pub indy: Entity<D>,
+    pub rock: Rocks<D>,
+    /// Zobrist hash of `grid`, updated incrementally by `apply`
+    pub zobrist: u64,
 }
 
-impl Node {
-    pub fn apply(&mut self, action: &Action) {
+impl<D: Dims> Node<D> {
+    /// Replaces the grid, recomputing its zobrist hash
+    pub fn set_grid(&mut self, grid: D::Grid) {
+        self.grid = grid;
+        self.zobrist = grid_zobrist::<D>(&grid);
+    }
+    /// Applies the action; the returned token reverts it
+    pub fn apply(&mut self, action: &Action<D>) -> Undo<D> {
         match action {
-            Action::Wait => {}
+            Action::Wait => Undo::Nothing,
             Action::Rotate { qa, rot } => {
-                self.grid[qa] = self.grid[qa].rotate(rot);
+                let old = self.grid[*qa];
+                self.set_cell(*qa, old.rotate(rot));
+                Undo::Cell(*qa, old)
+            }
+        }
+    }
+    /// Applies the action if the game rules allow it
+    pub fn try_apply(
+        &mut self,
+        params: &Params<D>,
+        action: &Action<D>,
+    ) -> Result<Undo<D>, IllegalAction<D>> {
+        action.check(params, self)?;
+        Ok(self.apply(action))
+    }
+    fn set_cell(&mut self, qa: D::Qa, cell: Cell) {
+        self.zobrist ^= zobrist_key(qa, self.grid[qa]) ^ zobrist_key(qa, cell);
+        self.grid[qa] = cell;
+    }
+    /// Token that reverts the indy and rock steps that follow, and the
+    /// rocks added after it
+    pub fn step_undo(&self) -> Undo<D> {
+        Undo::Step {
+            indy: self.indy,
+            rock: self.rock,
+        }
+    }
+    /// Reverts the change recorded in the token; tokens must be
+    /// undone in the reverse order in which they were created.
+    pub fn undo(&mut self, undo: Undo<D>) {
+        match undo {
+            Undo::Nothing => {}
+            Undo::Cell(qa, cell) => self.set_cell(qa, cell),
+            Undo::Step { indy, rock } => {
+                self.indy = indy;
+                self.rock = rock;
             }
         }
     }
@@ -388,19 +738,90 @@ impl Node {
         }
         false
     }
+    /// Moves all rocks, destroying the ones that can't enter the next
+    /// room and the ones that end up in the same room.
+    pub fn eval_rocks_step(&mut self) -> RockEnds<D> {
+        let mut ends = RockEnds::<D>::default();
+        for irock in D::IRock::iter() {
+            if let Some(rock) = self.rock[irock] {
+                let next = rock.step(self);
+                self.rock[irock] = match next {
+                    Some(next) if self.grid[next.qa].enter(&next.qr).is_some() => Some(next),
+                    Some(next) => {
+                        ends[irock] = Some(RockEnd::Wall(next.qa));
+                        None
+                    }
+                    None => {
+                        ends[irock] = Some(RockEnd::Wall(rock.qa));
+                        None
+                    }
+                };
+            }
+        }
+        for irock1 in D::IRock::iter() {
+            for irock2 in D::IRock::iter().filter(|irock2| *irock2 > irock1) {
+                if let (Some(rock1), Some(rock2)) = (self.rock[irock1], self.rock[irock2]) {
+                    if rock1.qa == rock2.qa {
+                        ends[irock1] = Some(RockEnd::Crash(irock2, rock1.qa));
+                        ends[irock2] = Some(RockEnd::Crash(irock1, rock2.qa));
+                    }
+                }
+            }
+        }
+        for irock in D::IRock::iter() {
+            if ends[irock].is_some() {
+                self.rock[irock] = None;
+            }
+        }
+        ends
+    }
+    /// Moves indy and then the rocks; returns false if indy can't
+    /// move.
     pub fn eval_all_step(&mut self) -> bool {
         if !self.eval_indy_step() {
             return false;
         }
-        for irock in IRock::iter() {
-            if let Some(rock) = self.rock[irock] {
-                self.rock[irock] = rock.step(self);
+        self.eval_rocks_step();
+        true
+    }
+    /// Puts the rock in the first free slot
+    pub fn add_rock(&mut self, rock: Entity<D>) -> Option<D::IRock> {
+        let irock = D::IRock::iter().find(|irock| self.rock[*irock].is_none())?;
+        self.rock[irock] = Some(rock);
+        Some(irock)
+    }
+    /// Replaces the rocks with the ones just read, keeping each rock
+    /// in the slot of the rock it continues from: the one whose
+    /// `Entity::step` on the current grid leads to it. The other rocks
+    /// just showed up; they go to free slots, which are returned.
+    /// Returns `None` if there are not enough slots.
+    pub fn track_rocks(&mut self, read: &[Entity<D>]) -> Option<Vec<D::IRock>> {
+        let mut rock = Rocks::<D>::default();
+        let mut spawned = vec![];
+        for entity in read {
+            let prev = D::IRock::iter().find(|irock| {
+                rock[*irock].is_none()
+                    && self.rock[*irock].and_then(|prev| prev.step(self)) == Some(*entity)
+            });
+            match prev {
+                Some(irock) => rock[irock] = Some(*entity),
+                None => spawned.push(*entity),
             }
         }
-        true
+        let mut new = vec![];
+        for entity in spawned {
+            // Prefer slots that were free in the previous turn too
+            let irock = D::IRock::iter()
+                .find(|irock| rock[*irock].is_none() && self.rock[*irock].is_none())
+                .or_else(|| D::IRock::iter().find(|irock| rock[*irock].is_none()))?;
+            rock[irock] = Some(entity);
+            new.push(irock);
+        }
+        self.rock = rock;
+        Some(new)
     }
-    pub fn has_rock_collision(&self) -> Option<IRock> {
-        for irock in IRock::iter() {
+    pub fn has_rock_collision(&self) -> Option<D::IRock> {
+        for irock in D::IRock::iter() {
             if let Some(rock) = self.rock[irock] {
                 if rock.qa == self.indy.qa {
                     return Some(irock);
@@ -411,7 +832,60 @@ impl Node {
     }
 }
 
-pub fn check_indy_path(params: &Params, node: &Node, indy: &Entity) -> bool {
+/// Reverts a change to a `Node`; see `Node::undo`
+#[derive(Debug, Clone, Copy)]
+pub enum Undo<D: Dims = Dims20> {
+    Nothing,
+    Cell(D::Qa, Cell),
+    Step { indy: Entity<D>, rock: Rocks<D> },
+}
+
+impl<D: Dims> PartialEq for Undo<D> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Undo::Nothing, Undo::Nothing) => true,
+            (Undo::Cell(qa1, cell1), Undo::Cell(qa2, cell2)) => qa1 == qa2 && cell1 == cell2,
+            (
+                Undo::Step {
+                    indy: indy1,
+                    rock: rock1,
+                },
+                Undo::Step {
+                    indy: indy2,
+                    rock: rock2,
+                },
+            ) => indy1 == indy2 && rock1.iter().eq(rock2.iter()),
+            _ => false,
+        }
+    }
+}
+
+impl<D: Dims> Eq for Undo<D> {}
+
+// The grid is hashed through its zobrist key and the rocks one by
+// one, which keeps the vendored grid and array types free of `Hash`
+impl<D: Dims> Hash for Node<D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.zobrist);
+        self.indy.hash(state);
+        for rock in self.rock.iter() {
+            rock.hash(state);
+        }
+    }
+}
+
+impl<D: Dims> PartialEq for Node<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.zobrist == other.zobrist
+            && self.grid == other.grid
+            && self.indy == other.indy
+            && self.rock.iter().eq(other.rock.iter())
+    }
+}
+
+impl<D: Dims> Eq for Node<D> {}
+
+pub fn check_indy_path<D: Dims>(params: &Params<D>, node: &Node<D>, indy: &Entity<D>) -> bool {
     if indy.qa == params.exit {
         return true;
     }
@@ -423,18 +897,91 @@ pub fn check_indy_path(params: &Params, node: &Node, indy: &Entity) -> bool {
     false
 }
 
+/* Reachability *****************************************************/
+
+/// The (room, entry direction) pairs from which the exit can still be
+/// reached, if the non-frozen rooms are rotated as needed
+///
+/// Each room is considered in isolation, so this is an
+/// over-approximation: every state left out is hopeless.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reach<D: Dims = Dims20> {
+    /// One grid for each of the `ENTRY_DIRS`
+    pub entries: [D::Gridbool; 3],
+}
+
+impl<D: Dims> Reach<D> {
+    /// Works backwards from `params.exit`, using the cells of
+    /// `params.grid0` and honouring `params.frozen`.
+    pub fn new(params: &Params<D>) -> Reach<D> {
+        let mut reach = Reach::default();
+        let mut queue = VecDeque::new();
+        for dir in ENTRY_DIRS {
+            reach.insert(params.exit, dir);
+            queue.push_back((params.exit, dir));
+        }
+        while let Some((qa, dir)) = queue.pop_front() {
+            let prev = match qa + -dir {
+                Some(prev) => prev,
+                None => continue,
+            };
+            let cell = params.grid0[prev];
+            let frozen = params.frozen[prev];
+            for orientation in cell.orientations().take(if frozen { 1 } else { 4 }) {
+                for entry in orientation.entries_for_exit(&dir) {
+                    if !reach.contains(prev, entry) {
+                        reach.insert(prev, entry);
+                        queue.push_back((prev, entry));
+                    }
+                }
+            }
+        }
+        reach
+    }
+
+    fn insert(&mut self, qa: D::Qa, dir: Qr) {
+        if let Some(i) = ENTRY_DIRS.iter().position(|d| *d == dir) {
+            self.entries[i].set_t(qa);
+        }
+    }
+
+    /// Whether the exit can be reached by entering `qa` moving in
+    /// direction `dir`
+    pub fn contains(&self, qa: D::Qa, dir: Qr) -> bool {
+        match ENTRY_DIRS.iter().position(|d| *d == dir) {
+            Some(i) => self.entries[i][qa],
+            None => false,
+        }
+    }
+
+    /// Whether the exit can be reached by the entity
+    pub fn reaches(&self, entity: &Entity<D>) -> bool {
+        self.contains(entity.qa, entity.qr)
+    }
+
+    /// The rooms from which the exit can be reached through some
+    /// entry, for debugging
+    pub fn overlay(&self) -> D::Gridbool {
+        D::Qa::iter()
+            .filter(|qa| self.entries.iter().any(|g| g[*qa]))
+            .collect()
+    }
+}
+
 // Complete simulation
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
-pub enum Destiny {
+pub enum Destiny<D: Dims = Dims20> {
     InvalidAction,
-    Wall(Qa),
-    Rock(IRock, Qa),
+    Wall(D::Qa),
+    Rock(D::IRock, D::Qa),
     Victory,
+    /// Indy is still alive when the simulation horizon is reached
+    Alive,
 }
 
-impl Destiny {
-    pub fn irock(&self) -> Option<IRock> {
+impl<D: Dims> Destiny<D> {
+    pub fn irock(&self) -> Option<D::IRock> {
         match self {
             Destiny::Rock(irock, _) => Some(*irock),
             _ => None,
@@ -442,21 +989,166 @@ impl Destiny {
     }
 }
 
-pub fn simulate(params: &Params, node0: &Node, mut steps: VecDeque<Action>) -> Destiny {
+/// A rock destroyed during a simulation
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct RockEvent<D: Dims = Dims20> {
+    pub turn: usize,
+    pub irock: D::IRock,
+    pub end: RockEnd<D>,
+}
+
+pub fn simulate<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    steps: VecDeque<Action<D>>,
+) -> Destiny<D> {
+    simulate_events(params, node0, steps, usize::MAX, |_| {})
+}
+
+/// Simulates only the given number of turns
+pub fn simulate_horizon<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    steps: VecDeque<Action<D>>,
+    horizon: usize,
+) -> Destiny<D> {
+    simulate_events(params, node0, steps, horizon, |_| {})
+}
+
+/// Simulates like [`simulate_horizon`], also returning the number
+/// of turns simulated
+pub fn simulate_turns<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    steps: VecDeque<Action<D>>,
+    horizon: usize,
+) -> (Destiny<D>, usize) {
+    simulate_full(params, node0, steps, horizon, |_| {})
+}
+
+/// Simulates like [`simulate_horizon`], also reporting each rock that
+/// gets destroyed along the way.
+pub fn simulate_events<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    steps: VecDeque<Action<D>>,
+    horizon: usize,
+    report: impl FnMut(RockEvent<D>),
+) -> Destiny<D> {
+    simulate_full(params, node0, steps, horizon, report).0
+}
+
+fn simulate_full<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    steps: VecDeque<Action<D>>,
+    horizon: usize,
+    report: impl FnMut(RockEvent<D>),
+) -> (Destiny<D>, usize) {
     let mut node = *node0;
-    while node.indy.qa != params.exit {
-        if let Some(action) = steps.pop_front() {
-            if action.qa() == Some(node.indy.qa) {
-                return Destiny::InvalidAction;
+    simulate_mut(params, &mut node, &steps, horizon, report)
+}
+
+/// Simulates like [`simulate_turns`], reporting the rocks destroyed
+/// like [`simulate_events`], on `node` itself: the changes are undone
+/// before returning, so that the callers that simulate many plans
+/// don't have to copy the node for each one.
+pub fn simulate_mut<D: Dims>(
+    params: &Params<D>,
+    node: &mut Node<D>,
+    steps: &VecDeque<Action<D>>,
+    horizon: usize,
+    mut report: impl FnMut(RockEvent<D>),
+) -> (Destiny<D>, usize) {
+    let step_undo = node.step_undo();
+    let mut undos = vec![];
+    let mut turn = 0;
+    let destiny = loop {
+        if node.indy.qa == params.exit {
+            break Destiny::Victory;
+        }
+        if turn == horizon {
+            break Destiny::Alive;
+        }
+        if let Some(action) = steps.get(turn) {
+            match node.try_apply(params, action) {
+                Ok(undo) => undos.push(undo),
+                Err(_) => break Destiny::InvalidAction,
             }
-            node.apply(&action);
         }
-        if !node.eval_all_step() {
-            return Destiny::Wall(node.indy.qa);
+        if !node.eval_indy_step() {
+            break Destiny::Wall(node.indy.qa);
+        }
+        let ends = node.eval_rocks_step();
+        for irock in D::IRock::iter() {
+            if let Some(end) = ends[irock] {
+                report(RockEvent { turn, irock, end });
+            }
         }
         if let Some(irock) = node.has_rock_collision() {
-            return Destiny::Rock(irock, node.indy.qa);
+            break Destiny::Rock(irock, node.indy.qa);
+        }
+        turn += 1;
+    };
+    for undo in undos.into_iter().rev() {
+        node.undo(undo);
+    }
+    node.undo(step_undo);
+    (destiny, turn)
+}
+
+/* Threats **********************************************************/
+
+/// A rock that could show up while a plan is followed, changing its
+/// outcome
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Threat<D: Dims = Dims20> {
+    /// Turn in which the rock shows up, counting from the start of
+    /// the plan
+    pub turn: usize,
+    pub rock: Entity<D>,
+    /// The node of that turn, with the rock
+    pub node: Node<D>,
+    /// What happens to indy then
+    pub destiny: Destiny<D>,
+}
+
+/// Where and when new rocks could enter the grid, see `rock_spawns`,
+/// and ruin the plan. The rocks of the first turn are already known,
+/// so the threats start at turn 1.
+pub fn threats<D: Dims>(
+    params: &Params<D>,
+    node0: &Node<D>,
+    mut steps: VecDeque<Action<D>>,
+) -> Vec<Threat<D>> {
+    let destiny0 = simulate(params, node0, steps.clone());
+    let mut threats = vec![];
+    let mut node = *node0;
+    for turn in 1.. {
+        let action = steps.pop_front().unwrap_or(Action::Wait);
+        if node.try_apply(params, &action).is_err()
+            || !node.eval_all_step()
+            || node.has_rock_collision().is_some()
+            || node.indy.qa == params.exit
+        {
+            break;
+        }
+        for rock in rock_spawns(params, &node) {
+            let undo = node.step_undo();
+            if node.add_rock(rock).is_none() {
+                continue;
+            }
+            let (destiny, _) = simulate_mut(params, &mut node, &steps, usize::MAX, |_| {});
+            if destiny != destiny0 {
+                threats.push(Threat {
+                    turn,
+                    rock,
+                    node,
+                    destiny,
+                });
+            }
+            node.undo(undo);
         }
     }
-    Destiny::Victory
+    threats
 }
@@ -0,0 +1,136 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Differential testing of the production solver against the oracle
+//!
+//! Each level is played turn by turn with one of the solvers, as the
+//! bots do, and decided with `oracle::oracle`. Levels that the oracle
+//! wins and the solver loses are bugs, unless the solver lost to rocks
+//! it couldn't know about yet, and can be saved as level files to be
+//! reproduced.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time;
+
+use super::core::*;
+use super::entrypoint2::{Budget, Plan, PlanCache, SolveError};
+use super::error::Error;
+use super::level::Level;
+use super::oracle::oracle;
+use super::referee::{Game, Outcome};
+
+/// The outcomes of the solver and of the oracle on a level
+#[derive(Debug)]
+pub struct Report<D: Dims = Dims20> {
+    pub outcome: Outcome<D>,
+    pub oracle: Result<Plan<D>, SolveError>,
+    /// The oracle on the game as the solver knew it when it lost: from
+    /// the last turn before the defeat in which rocks showed up, and
+    /// without the rocks that show up later, which the oracle knows
+    /// about from the start but the solver can't. `None` if the solver
+    /// won.
+    pub known: Option<Result<Plan<D>, SolveError>>,
+}
+
+impl<D: Dims> Report<D> {
+    /// Whether the solver lost a level that can be won, and that it
+    /// could have won with the rocks it knew about
+    pub fn missed(&self) -> bool {
+        self.oracle.is_ok() && matches!(self.known, Some(Ok(_)))
+    }
+}
+
+/// Plays the level with the solver as the bot does: through a
+/// `PlanCache`, reading indy and the rocks each turn and giving the
+/// solver `turn_time` per turn.
+pub fn play_solver<D: Dims>(
+    level: &Level<D>,
+    turn_time: time::Duration,
+    solve: impl FnMut(&Params<D>, &Node<D>, &mut Budget) -> Result<Plan<D>, SolveError>,
+) -> Result<Outcome<D>, Error> {
+    Ok(play_known(level, turn_time, solve)?.0)
+}
+
+/// Plays like `play_solver`, also returning the node at the start of
+/// the last turn in which rocks showed up
+fn play_known<D: Dims>(
+    level: &Level<D>,
+    turn_time: time::Duration,
+    mut solve: impl FnMut(&Params<D>, &Node<D>, &mut Budget) -> Result<Plan<D>, SolveError>,
+) -> Result<(Outcome<D>, Node<D>), Error> {
+    let params = &level.params;
+    let mut game = Game::new(level)?;
+    let mut cache = PlanCache {
+        contingencies: true,
+        ..Default::default()
+    };
+    let mut node = game.node;
+    let mut known = game.node;
+    loop {
+        if level.rocks_at(game.turn).next().is_some() {
+            known = game.node;
+        }
+        // What input_ep2 reads
+        let read = game.node.rock.iter().flatten().copied().collect::<Vec<_>>();
+        node.indy = game.node.indy;
+        node.track_rocks(&read)
+            .ok_or(Error::TooManyRocks(read.len()))?;
+        let mut budget = Budget::new(turn_time);
+        let action = cache.turn(params, &mut node, &mut budget, &mut solve);
+        if let Some(outcome) = game.play(action)? {
+            return Ok((outcome, known));
+        }
+    }
+}
+
+/// Plays the level with `solve` and decides it with the oracle,
+/// exploring up to `max_states` states
+pub fn difftest<D: Dims>(
+    level: &Level<D>,
+    turn_time: time::Duration,
+    max_states: usize,
+    solve: impl FnMut(&Params<D>, &Node<D>, &mut Budget) -> Result<Plan<D>, SolveError>,
+) -> Result<Report<D>, Error> {
+    let (outcome, node) = play_known(level, turn_time, solve)?;
+    let decided = oracle(level, max_states);
+    let known = match outcome {
+        Outcome::Victory(_) => None,
+        _ if level.rocks.is_empty() => Some(decided.clone()),
+        _ => {
+            let known = Level {
+                params: Params {
+                    width: level.params.width,
+                    height: level.params.height,
+                    exit: level.params.exit,
+                    frozen: level.params.frozen,
+                    grid0: level.params.grid0,
+                },
+                node,
+                rocks: vec![],
+            };
+            Some(oracle(&known, max_states))
+        }
+    };
+    Ok(Report {
+        outcome,
+        oracle: decided,
+        known,
+    })
+}
+
+/// Saves the level in `dir`, named after the hash of its contents so
+/// that the same level is saved only once; returns the path.
+pub fn save_level<D: Dims>(level: &Level<D>, dir: &Path) -> io::Result<PathBuf> {
+    let contents = level.to_string();
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("missed-{:016x}.txt", hasher.finish()));
+    fs::write(&path, contents)?;
+    Ok(path)
+}
@@ -9,7 +9,7 @@ use std::io::BufRead;
 use super::core::*;
 use super::input::*;
 
-pub fn eval(_params: &Params, node: &Node) -> Qa {
+pub fn eval<D: Dims>(_params: &Params<D>, node: &Node<D>) -> D::Qa {
     let dir = node.grid[node.indy.qa]
         .enter(&node.indy.qr)
         .expect("invalid indy direction");
@@ -17,7 +17,7 @@ pub fn eval(_params: &Params, node: &Node) -> Qa {
 }
 
 pub fn main() -> Result<(), Box<dyn Error>> {
-    let mut params = Params::default();
+    let mut params = Params::<Dims20>::default();
     let mut node = Node::default();
     let stdin = io::stdin();
     let mut stdin_lines = stdin.lock().lines();
@@ -2,82 +2,788 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::env;
 use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::BufRead;
+use std::time;
 
 use super::core::*;
 use super::input::*;
 
-pub fn solve_helper(
-    params: &Params,
-    node0: &Node,
-    focus0: Entity,
+/// Maximum number of search nodes visited by a single `solve` call,
+/// as a safety net for the time budget
+pub const MAX_NODES: usize = 200000;
+
+/// Time budget of each turn; the game allows 150ms
+pub const TURN_TIME: time::Duration = time::Duration::from_millis(100);
+
+/// Time budget of the first turn; the game allows 1s
+pub const FIRST_TURN_TIME: time::Duration = time::Duration::from_millis(800);
+
+/// Number of turns searched by the first round of `deepen`
+pub const HORIZON0: usize = 8;
+
+/* Budget ***********************************************************/
+
+/// Search budget: a deadline and a maximum number of search nodes
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub deadline: time::Instant,
+    pub nodes: usize,
+}
+
+impl Budget {
+    pub fn new(duration: time::Duration) -> Budget {
+        Budget {
+            deadline: time::Instant::now() + duration,
+            nodes: MAX_NODES,
+        }
+    }
+
+    /// Budget of a game turn, starting now
+    ///
+    /// The durations can be overridden for local runs with the
+    /// `THE_FALL_TURN_MS` and `THE_FALL_FIRST_TURN_MS` environment
+    /// variables.
+    pub fn for_turn(first: bool) -> Budget {
+        let (var, default) = if first {
+            ("THE_FALL_FIRST_TURN_MS", FIRST_TURN_TIME)
+        } else {
+            ("THE_FALL_TURN_MS", TURN_TIME)
+        };
+        let duration = env::var(var)
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map(time::Duration::from_millis)
+            .unwrap_or(default);
+        Budget::new(duration)
+    }
+
+    /// Spends one search node; returns false if the budget is over.
+    /// The clock is only checked every few nodes.
+    pub fn spend(&mut self) -> bool {
+        if self.nodes == 0 {
+            return false;
+        }
+        self.nodes -= 1;
+        if self.nodes.is_multiple_of(256) && time::Instant::now() >= self.deadline {
+            self.nodes = 0;
+        }
+        true
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.nodes == 0
+    }
+}
+
+/* Plan *************************************************************/
+
+/// A solution found by the solver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan<D: Dims = Dims20> {
+    /// One action per turn, starting with the current one
+    pub steps: VecDeque<Action<D>>,
+    /// What happens to indy if the plan is followed; `Destiny::Alive`
+    /// if the plan ends before the exit
+    pub destiny: Destiny<D>,
+    /// Turns until indy gets to the exit, if the plan gets there
+    pub turns: Option<usize>,
+    /// Number of rotations in the plan
+    pub rotations: usize,
+}
+
+impl<D: Dims> Plan<D> {
+    /// Simulates `steps` up to `horizon` to fill in the plan
+    pub fn new(
+        params: &Params<D>,
+        node: &Node<D>,
+        steps: VecDeque<Action<D>>,
+        horizon: usize,
+    ) -> Plan<D> {
+        let (destiny, turns) = simulate_turns(params, node, steps.clone(), horizon);
+        Plan {
+            rotations: steps.iter().filter(|a| **a != Action::Wait).count(),
+            steps,
+            destiny,
+            turns: (destiny == Destiny::Victory).then_some(turns),
+        }
+    }
+
+    /// The action of the current turn
+    pub fn action(&self) -> Action<D> {
+        self.steps.front().copied().unwrap_or(Action::Wait)
+    }
+}
+
+/// Why the solver couldn't find a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolveError {
+    /// No rotations lead indy to the exit
+    NoPath,
+    /// Every path found hits the rock in this slot
+    UnavoidableRock(usize),
+    /// Every path found needs a rotation that the game doesn't allow,
+    /// such as one of a room with a rock in it
+    InvalidAction,
+    /// The budget ran out before any plan was found
+    BudgetExceeded,
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::NoPath => write!(f, "no path to the exit"),
+            SolveError::UnavoidableRock(irock) => write!(f, "rock {} can't be avoided", irock),
+            SolveError::InvalidAction => write!(f, "every path needs an invalid action"),
+            SolveError::BudgetExceeded => write!(f, "budget exceeded"),
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+/* Transposition table **********************************************/
+
+/// Hashes of the search states already proven dead
+pub type TransTable = HashSet<u64>;
+
+/// Hash of a search state: the node, the rotations already scheduled
+/// in `steps` and whatever else the search depends on
+pub fn state_key<D: Dims>(node: &Node<D>, steps: &VecDeque<Action<D>>, extra: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node.hash(&mut hasher);
+    for (iturn, action) in steps.iter().enumerate() {
+        if *action != Action::Wait {
+            (iturn, action).hash(&mut hasher);
+        }
+    }
+    extra.hash(&mut hasher);
+    hasher.finish()
+}
+
+/* Search ***********************************************************/
+
+/// Returns `num` free (WAIT) turns up to and including `last`,
+/// latest first, growing `steps` as needed.
+pub fn free_turns<D: Dims>(
+    steps: &mut VecDeque<Action<D>>,
+    last: usize,
+    num: usize,
+) -> Option<Vec<usize>> {
+    if steps.len() <= last {
+        steps.resize(last + 1, Action::Wait);
+    }
+    let turns = (0..=last)
+        .rev()
+        .filter(|&iturn| steps[iturn] == Action::Wait)
+        .take(num)
+        .collect::<Vec<_>>();
+    if turns.len() == num {
+        Some(turns)
+    } else {
+        None
+    }
+}
+
+/// Depth-first search over the rooms in indy's path
+///
+/// `focus0` is where indy is at the start of turn `iturn`, and `node`
+/// has the orientation that each room will have when indy gets to
+/// it. `steps` has one action per turn: the rotations of the next
+/// room go to the latest free turns before indy enters it, which can
+/// be several turns ahead of indy. `check` gets each plan that
+/// reaches the exit or survives up to turn `horizon`, along with the
+/// number of turns it covers (`usize::MAX` if it reaches the exit),
+/// and decides if the search should stop. States that fail are
+/// stored in `tt` and not searched again, and the ones that `reach`
+/// rules out are not searched at all.
+///
+/// The rotations are applied to `node` in place and undone on the
+/// way back, so that the search doesn't copy the grid.
+#[allow(clippy::too_many_arguments)]
+pub fn solve_helper<D: Dims>(
+    params: &Params<D>,
+    reach: &Reach<D>,
+    node: &mut Node<D>,
+    focus0: Entity<D>,
     iturn: usize,
-    steps: &mut VecDeque<Action>,
+    horizon: usize,
+    steps: &mut VecDeque<Action<D>>,
+    budget: &mut Budget,
+    tt: &mut TransTable,
+    check: &mut impl FnMut(&mut VecDeque<Action<D>>, usize) -> bool,
 ) -> bool {
-    if check_indy_path(params, node0, &focus0) {
-        eprintln!("SUCCESS");
-        steps.push_front(Action::Wait);
-        return true;
-    }
-    for actions in Action::available(params, node0, &focus0) {
-        let mut node = *node0;
-        for a in &actions {
-            node.apply(a);
-        }
-        if let Some(focus) = focus0.step(&node) {
-            if solve_helper(params, &node, focus, iturn + 1, steps) {
-                for a in &actions {
-                    steps.push_front(*a);
+    if focus0.qa == params.exit {
+        return check(steps, usize::MAX);
+    }
+    if iturn == horizon {
+        return check(steps, horizon);
+    }
+    let key = state_key(node, steps, (focus0, iturn, horizon));
+    if tt.contains(&key) || !budget.spend() {
+        return false;
+    }
+    let next = match focus0.step(node) {
+        Some(next) if reach.reaches(&next) => next,
+        _ => return false,
+    };
+    for actions in Action::available(params, node, &next.qa) {
+        let undos = actions.iter().map(|a| node.apply(a)).collect::<Vec<_>>();
+        let turns = if next.qa == params.exit || node.grid[next.qa].enter(&next.qr).is_some() {
+            free_turns(steps, iturn, actions.len())
+        } else {
+            None
+        };
+        let mut found = false;
+        if let Some(turns) = turns {
+            for (iturn, a) in turns.iter().zip(actions.iter()) {
+                steps[*iturn] = *a;
+            }
+            found = solve_helper(
+                params,
+                reach,
+                node,
+                next,
+                iturn + 1,
+                horizon,
+                steps,
+                budget,
+                tt,
+                check,
+            );
+            if !found {
+                for iturn in turns {
+                    steps[iturn] = Action::Wait;
                 }
-                return true;
             }
         }
+        for undo in undos.into_iter().rev() {
+            node.undo(undo);
+        }
+        if found {
+            return true;
+        }
+    }
+    if !budget.is_over() {
+        tt.insert(key);
     }
     false
 }
 
-pub fn rock_solve(
-    params: &Params,
-    node: &Node,
-    mut steps: VecDeque<Action>,
-    irock: IRock,
-    qa_collision: Qa,
-) -> Option<Action> {
-    for focus in node.rock[irock].unwrap().iter(node) {
-        if focus.qa == qa_collision {
-            return None;
+/// Maximum number of rocks derailed by `rock_solve` in a single plan
+pub const MAX_DERAILS: usize = 3;
+
+/// The rooms that the rock enters and the turns in which it does,
+/// until it's destroyed or hits indy; `node` is left as it was.
+pub fn rock_path<D: Dims>(
+    node: &mut Node<D>,
+    steps: &VecDeque<Action<D>>,
+    irock: D::IRock,
+) -> Vec<(usize, D::Qa)> {
+    let step_undo = node.step_undo();
+    let mut undos = vec![];
+    let mut path = vec![];
+    for iturn in 0..D::Qa::SIZE {
+        undos.push(node.apply(steps.get(iturn).unwrap_or(&Action::Wait)));
+        if !node.eval_all_step() {
+            break;
         }
-        if params.frozen[focus.qa] || node.grid[focus.qa].num_rot() == 0 {
-            continue;
+        match node.rock[irock] {
+            Some(rock) => {
+                path.push((iturn, rock.qa));
+                if rock.qa == node.indy.qa {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    for undo in undos.into_iter().rev() {
+        node.undo(undo);
+    }
+    node.undo(step_undo);
+    path
+}
+
+/// Derails the rocks that hit indy
+///
+/// Tries every rotation sequence of every room that the first rock to
+/// hit indy enters, using the free turns of `steps` before the rock
+/// gets there, as well as bringing forward the rotations that the
+/// plan already has for those rooms, and then recurses for the next
+/// rock, up to `depth` rocks. Rotations of rooms with a rock in them
+/// are turned the other way round or moved to other turns, which also
+/// counts towards `depth`.
+/// Each plan is simulated as a whole, on `node`, which is left as it
+/// was, and indy only has to survive up to turn `horizon`. The same
+/// plan is often reached by derailing rocks in different orders, so
+/// failed ones are stored in `tt`.
+pub fn rock_solve<D: Dims>(
+    params: &Params<D>,
+    node: &mut Node<D>,
+    steps: &mut VecDeque<Action<D>>,
+    horizon: usize,
+    depth: usize,
+    budget: &mut Budget,
+    tt: &mut TransTable,
+) -> bool {
+    let key = state_key(node, steps, (horizon, depth));
+    if tt.contains(&key) || !budget.spend() {
+        return false;
+    }
+    let (destiny, turn) = simulate_mut(params, node, steps, horizon, |_| {});
+    let irock = match destiny {
+        Destiny::Victory | Destiny::Alive => return true,
+        Destiny::Rock(irock, _) if depth > 0 => irock,
+        Destiny::InvalidAction if depth > 0 => {
+            // A rock is in the room when it's rotated. Half turns can
+            // go the other way round, which may keep the rock out;
+            // otherwise the rotation trades turns with a WAIT or with
+            // another rotation.
+            let action = steps[turn];
+            let room = (0..steps.len())
+                .filter(|&iturn| steps[iturn].qa() == action.qa())
+                .collect::<Vec<_>>();
+            if let Action::Rotate { qa, rot } = action {
+                let half = room.len() == 2 && room.iter().all(|iturn| steps[*iturn] == action);
+                if half && node.grid[qa].num_rot() == 3 {
+                    let other = match rot {
+                        Rotation::Left => Rotation::Right,
+                        Rotation::Right => Rotation::Left,
+                    };
+                    for iturn in &room {
+                        steps[*iturn] = Action::new(qa, other);
+                    }
+                    if rock_solve(params, node, steps, horizon, depth - 1, budget, tt) {
+                        return true;
+                    }
+                    for iturn in &room {
+                        steps[*iturn] = action;
+                    }
+                }
+            }
+            for iturn in (0..steps.len()).filter(|&iturn| iturn != turn) {
+                steps.swap(turn, iturn);
+                if rock_solve(params, node, steps, horizon, depth - 1, budget, tt) {
+                    return true;
+                }
+                steps.swap(turn, iturn);
+            }
+            if !budget.is_over() {
+                tt.insert(key);
+            }
+            return false;
         }
-        let action = Action::new(focus.qa, Rotation::Left);
-        steps[0] = action;
-        if simulate(params, node, steps.clone()).irock() != Some(irock) {
-            return Some(action);
+        _ => return false,
+    };
+    for (iturn, qa) in rock_path(node, steps, irock) {
+        // The rotations that the plan already has for the room can be
+        // brought forward, to derail the rock on its way
+        let later = (iturn + 1..steps.len())
+            .filter(|&iturn| steps[iturn].qa() == Some(qa))
+            .collect::<Vec<_>>();
+        if !later.is_empty() {
+            let plan = steps.clone();
+            for iturn in &later {
+                steps[*iturn] = Action::Wait;
+            }
+            if let Some(turns) = free_turns(steps, iturn, later.len()) {
+                for (iturn, later) in turns.iter().zip(later.iter()) {
+                    steps[*iturn] = plan[*later];
+                }
+                if rock_solve(params, node, steps, horizon, depth - 1, budget, tt) {
+                    return true;
+                }
+            }
+            *steps = plan;
         }
+        for actions in Action::available(params, node, &qa).into_iter().skip(1) {
+            let latest = match free_turns(steps, iturn, actions.len()) {
+                Some(turns) => turns,
+                None => continue,
+            };
+            let mut earliest = (0..=iturn)
+                .filter(|&iturn| steps[iturn] == Action::Wait)
+                .take(actions.len())
+                .collect::<Vec<_>>();
+            earliest.reverse();
+            let mut options = vec![latest];
+            if earliest != options[0] {
+                options.push(earliest);
+            }
+            for turns in options {
+                for (iturn, a) in turns.iter().zip(actions.iter()) {
+                    steps[*iturn] = *a;
+                }
+                if rock_solve(params, node, steps, horizon, depth - 1, budget, tt) {
+                    return true;
+                }
+                for iturn in turns {
+                    steps[iturn] = Action::Wait;
+                }
+            }
+        }
+    }
+    if !budget.is_over() {
+        tt.insert(key);
     }
-    None
+    false
 }
 
-pub fn solve(params: &Params, node: &Node) -> Option<Action> {
-    let mut stepsbase = VecDeque::new();
-    let focus = node.indy.step(node)?;
-    if !solve_helper(params, node, focus, 0, &mut stepsbase) {
-        return None;
+/// Iterative deepening over `solve_helper`
+///
+/// Searches for plans that keep indy alive for `HORIZON0` turns,
+/// then for twice that, and so on, until a plan gets to the exit.
+/// Returns the plan along with the number of turns it covers
+/// (`usize::MAX` if it reaches the exit); when the budget runs out,
+/// that is the best plan of the previous rounds, if any. The dead states of
+/// the previous rounds are kept, as they are keyed by horizon.
+pub fn deepen<D: Dims>(
+    params: &Params<D>,
+    node: &Node<D>,
+    budget: &mut Budget,
+    check: &mut impl FnMut(&mut VecDeque<Action<D>>, usize) -> bool,
+) -> Result<(VecDeque<Action<D>>, usize), SolveError> {
+    let mut best = None;
+    let mut tt = TransTable::new();
+    let reach = Reach::new(params);
+    let mut search_node = *node;
+    let mut horizon = HORIZON0;
+    loop {
+        if horizon >= D::Qa::SIZE {
+            horizon = usize::MAX;
+        }
+        let mut steps = VecDeque::new();
+        let mut covered = 0;
+        let found = solve_helper(
+            params,
+            &reach,
+            &mut search_node,
+            node.indy,
+            0,
+            horizon,
+            &mut steps,
+            budget,
+            &mut tt,
+            &mut |steps, turns| {
+                covered = turns;
+                check(steps, turns)
+            },
+        );
+        if !found {
+            return match best {
+                Some(best) if budget.is_over() => Ok(best),
+                _ if budget.is_over() => Err(SolveError::BudgetExceeded),
+                _ => Err(SolveError::NoPath),
+            };
+        }
+        if covered == usize::MAX {
+            return Ok((steps, covered));
+        }
+        best = Some((steps, covered));
+        horizon *= 2;
     }
-    stepsbase.push_front(Action::Wait);
-    match simulate(params, node, stepsbase.clone()) {
-        Destiny::Victory => Some(Action::Wait),
-        Destiny::Rock(irock, qa) => rock_solve(params, node, stepsbase, irock, qa),
-        _ => Some(stepsbase[1]),
+}
+
+/// Looks for a plan that gets indy to the exit, checking each one
+/// against the rocks until one survives
+pub fn solve<D: Dims>(
+    params: &Params<D>,
+    node: &Node<D>,
+    budget: &mut Budget,
+) -> Result<Plan<D>, SolveError> {
+    let mut rock_node = *node;
+    let mut rock_budget = *budget;
+    let mut rock_tt = TransTable::new();
+    let mut rejected = None;
+    let result = deepen(params, node, budget, &mut |steps, horizon| {
+        let plan = steps.clone();
+        let ok = rock_solve(
+            params,
+            &mut rock_node,
+            steps,
+            horizon,
+            MAX_DERAILS,
+            &mut rock_budget,
+            &mut rock_tt,
+        );
+        if !ok {
+            *steps = plan.clone();
+            rejected = Some((plan, horizon));
+        }
+        ok
+    });
+    match result {
+        Ok((mut steps, horizon)) => {
+            if steps.is_empty() {
+                steps.push_back(Action::Wait);
+            }
+            Ok(Plan::new(params, node, steps, horizon))
+        }
+        Err(SolveError::NoPath) => match rejected {
+            Some((steps, horizon)) => Err(unsolved(params, node, steps, horizon)),
+            None => Err(SolveError::NoPath),
+        },
+        Err(e) => Err(e),
     }
 }
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let mut params = Params::default();
+/// The reason why a plan that gets indy to the exit was rejected
+pub fn unsolved<D: Dims>(
+    params: &Params<D>,
+    node: &Node<D>,
+    steps: VecDeque<Action<D>>,
+    horizon: usize,
+) -> SolveError {
+    match simulate_horizon(params, node, steps, horizon) {
+        Destiny::Rock(irock, _) => SolveError::UnavoidableRock(irock.into()),
+        Destiny::InvalidAction => SolveError::InvalidAction,
+        _ => SolveError::NoPath,
+    }
+}
+
+/// Number of turns indy survives after the action if nothing else is
+/// done; `usize::MAX` if he gets to the exit. `node` is left as it
+/// was.
+pub fn survival<D: Dims>(params: &Params<D>, node: &mut Node<D>, action: &Action<D>) -> usize {
+    let step_undo = node.step_undo();
+    let undo = node.apply(action);
+    let mut turns = D::Qa::SIZE;
+    for iturn in 0..D::Qa::SIZE {
+        if node.indy.qa == params.exit {
+            turns = usize::MAX;
+            break;
+        }
+        if !node.eval_all_step() || node.has_rock_collision().is_some() {
+            turns = iturn;
+            break;
+        }
+    }
+    node.undo(undo);
+    node.undo(step_undo);
+    turns
+}
+
+/// Action to play when `solve` can't find a solution: the legal one
+/// that keeps indy alive for longer, as a later rock or rotation may
+/// still clear the way.
+pub fn fallback<D: Dims>(params: &Params<D>, node: &Node<D>) -> Action<D> {
+    let mut sim_node = *node;
+    let mut best = (survival(params, &mut sim_node, &Action::Wait), Action::Wait);
+    for qa in D::Qa::iter() {
+        for actions in Action::available(params, node, &qa)
+            .into_iter()
+            .skip(1)
+            .take(2)
+        {
+            let action = actions[0];
+            if !action.is_valid(params, node) {
+                continue;
+            }
+            let turns = survival(params, &mut sim_node, &action);
+            if turns > best.0 {
+                best = (turns, action);
+            }
+        }
+    }
+    best.1
+}
+
+/// Applies the action to `node` and returns it, or WAIT if the game
+/// wouldn't accept it, so that we never print an illegal command
+pub fn checked_apply<D: Dims>(
+    params: &Params<D>,
+    node: &mut Node<D>,
+    action: Action<D>,
+) -> Action<D> {
+    match node.try_apply(params, &action) {
+        Ok(_) => action,
+        Err(e) => {
+            eprintln!("refusing to play {}: {}", action, e);
+            Action::Wait
+        }
+    }
+}
+
+/* Main loop ********************************************************/
+
+/// Keeps the plan across turns, re-planning only when the game
+/// doesn't behave as predicted
+///
+/// With `contingencies` set, the first turn also solves in advance
+/// each rock arrival that would break the plan, so that later turns
+/// only have to pick the matching branch.
+#[derive(Debug, Default)]
+pub struct PlanCache<D: Dims = Dims20> {
+    /// The rest of the plan being followed; only plans that get indy
+    /// to the exit are kept
+    pub plan: Option<Plan<D>>,
+    /// The node we expect to read at the start of the next turn
+    pub expected: Option<Node<D>>,
+    /// Number of turns played
+    pub turn: usize,
+    pub contingencies: bool,
+    /// Plans for a rock showing up at the given turn, valid while
+    /// `plan` is followed
+    pub branches: HashMap<(usize, Entity<D>), Plan<D>>,
+}
+
+impl<D: Dims> PlanCache<D> {
+    /// Compares the node just read with the prediction. New rocks
+    /// are expected; if one breaks the plan, we switch to its branch.
+    /// Anything else is a desync: we log it and drop the plan.
+    pub fn sync(&mut self, params: &Params<D>, node: &Node<D>) -> bool {
+        let expected = match self.expected.take() {
+            Some(expected) => expected,
+            None => return true,
+        };
+        // Rocks keep their slots across turns, see `input_ep2`
+        let mut new = vec![];
+        let mut desync = expected.indy != node.indy;
+        for irock in D::IRock::iter() {
+            match (expected.rock[irock], node.rock[irock]) {
+                (None, Some(rock)) => new.push(rock),
+                (predicted, read) => desync |= predicted != read,
+            }
+        }
+        if desync {
+            eprintln!(
+                "desync: expected indy {} rocks {}, got indy {} rocks {}",
+                expected.indy,
+                rocks_str::<D>(&expected.rock),
+                node.indy,
+                rocks_str::<D>(&node.rock)
+            );
+            self.set_plan(None);
+            return false;
+        }
+        if new.is_empty() {
+            return true;
+        }
+        for rock in &new {
+            eprintln!("new rock {}", rock);
+        }
+        let works = |plan: &Plan<D>| simulate(params, node, plan.steps.clone()) == Destiny::Victory;
+        if self.plan.as_ref().is_some_and(works) {
+            return true;
+        }
+        let branch = match new[..] {
+            [rock] => self.branches.remove(&(self.turn, rock)),
+            _ => None,
+        };
+        self.set_plan(branch.filter(works));
+        true
+    }
+
+    fn set_plan(&mut self, plan: Option<Plan<D>>) {
+        self.plan = plan;
+        self.branches.clear();
+    }
+
+    /// Solves in advance, while the budget lasts, the `threats` to the
+    /// plan: the rocks that would break it if they showed up in one
+    /// of its turns
+    pub fn precompute(
+        &mut self,
+        params: &Params<D>,
+        node0: &Node<D>,
+        budget: &Budget,
+        solve: &mut impl FnMut(&Params<D>, &Node<D>, &mut Budget) -> Result<Plan<D>, SolveError>,
+    ) {
+        let steps = match &self.plan {
+            Some(plan) => plan.steps.clone(),
+            None => return,
+        };
+        for threat in threats(params, node0, steps) {
+            if time::Instant::now() >= budget.deadline {
+                return;
+            }
+            let mut branch_budget = Budget {
+                nodes: MAX_NODES,
+                ..*budget
+            };
+            if let Ok(plan) = solve(params, &threat.node, &mut branch_budget) {
+                if plan.destiny == Destiny::Victory {
+                    self.branches
+                        .insert((self.turn + threat.turn, threat.rock), plan);
+                }
+            }
+        }
+    }
+
+    /// Plays a turn: syncs with `node`, takes the next action from
+    /// the cached plan or from a new one, applies it to `node` and
+    /// predicts the next turn. Returns the command to print.
+    pub fn turn(
+        &mut self,
+        params: &Params<D>,
+        node: &mut Node<D>,
+        budget: &mut Budget,
+        mut solve: impl FnMut(&Params<D>, &Node<D>, &mut Budget) -> Result<Plan<D>, SolveError>,
+    ) -> Action<D> {
+        self.sync(params, node);
+        if self.plan.is_none() {
+            match solve(params, node, budget) {
+                Ok(plan) if plan.destiny == Destiny::Victory => {
+                    self.set_plan(Some(plan));
+                    if self.contingencies && self.turn == 0 {
+                        self.precompute(params, node, budget, &mut solve);
+                    }
+                }
+                Ok(plan) => return self.play(params, node, plan.action()),
+                Err(e) => {
+                    eprintln!("could not find solution: {}", e);
+                    let action = fallback(params, node);
+                    return self.play(params, node, action);
+                }
+            }
+        }
+        let action = match &mut self.plan {
+            Some(plan) => plan.steps.pop_front().unwrap_or(Action::Wait),
+            None => Action::Wait,
+        };
+        self.play(params, node, action)
+    }
+
+    fn play(&mut self, params: &Params<D>, node: &mut Node<D>, action: Action<D>) -> Action<D> {
+        let played = checked_apply(params, node, action);
+        if played != action {
+            self.set_plan(None);
+        }
+        let mut expected = *node;
+        self.expected = expected.eval_all_step().then_some(expected);
+        self.turn += 1;
+        played
+    }
+}
+
+fn rocks_str<D: Dims>(rocks: &Rocks<D>) -> String {
+    let rocks = rocks
+        .iter()
+        .flatten()
+        .map(|rock| rock.to_string())
+        .collect::<Vec<_>>();
+    format!("[{}]", rocks.join(" "))
+}
+
+/// The bot's main loop: reads the input of each turn and prints the
+/// action of the plan that `solve` comes up with
+pub fn run(
+    mut solve: impl FnMut(
+        &Params<Dims20>,
+        &Node<Dims20>,
+        &mut Budget,
+    ) -> Result<Plan<Dims20>, SolveError>,
+) -> Result<(), Box<dyn Error>> {
+    let mut params = Params::<Dims20>::default();
     let mut node = Node::default();
     let stdin = io::stdin();
     let mut stdin_lines = stdin.lock().lines();
@@ -86,13 +792,19 @@ pub fn main() -> Result<(), Box<dyn Error>> {
         "Dimensions: {}x{}; exit {}",
         params.width, params.height, params.exit
     );
-    loop {
+    let mut cache = PlanCache {
+        contingencies: true,
+        ..Default::default()
+    };
+    for turn in 0.. {
         input_ep2(&mut stdin_lines, &params, &mut node)?;
-        if let Some(action) = solve(&params, &node) {
-            println!("{}", action);
-            node.apply(&action);
-        } else {
-            eprintln!("could not find solution");
-        }
+        let mut budget = Budget::for_turn(turn == 0);
+        let action = cache.turn(&params, &mut node, &mut budget, &mut solve);
+        println!("{}", action);
     }
+    Ok(())
+}
+
+pub fn main() -> Result<(), Box<dyn Error>> {
+    run(solve)
 }
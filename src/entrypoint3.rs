@@ -0,0 +1,148 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::collections::VecDeque;
+use std::error::Error;
+
+use super::core::*;
+use super::entrypoint2::{
+    deepen, free_turns, rock_path, rock_solve, run, unsolved, Budget, Plan, SolveError, TransTable,
+    MAX_DERAILS,
+};
+
+/// Number of plans with threats that `solve` goes through while
+/// looking for one without
+pub const MAX_THREATENED: usize = 8;
+
+/// Rotates rooms ahead of the rocks that could show up and ruin the
+/// plan, while that lowers the number of `threats` and the plan still
+/// wins. Returns the number of threats left.
+pub fn defuse<D: Dims>(
+    params: &Params<D>,
+    node: &Node<D>,
+    steps: &mut VecDeque<Action<D>>,
+    budget: &mut Budget,
+) -> usize {
+    let mut current = threats(params, node, steps.clone());
+    'improve: while !current.is_empty() {
+        for threat in &current {
+            let irock = match D::IRock::iter().find(|i| threat.node.rock[*i] == Some(threat.rock)) {
+                Some(irock) => irock,
+                None => continue,
+            };
+            // The spawn room must be rotated before the rock shows up,
+            // the others before it gets to them
+            let rest = steps.iter().skip(threat.turn).copied().collect();
+            let mut threat_node = threat.node;
+            let path = rock_path(&mut threat_node, &rest, irock)
+                .into_iter()
+                .map(|(iturn, qa)| (threat.turn + iturn, qa));
+            for (last, qa) in [(threat.turn - 1, threat.rock.qa)].into_iter().chain(path) {
+                for actions in Action::available(params, node, &qa).into_iter().skip(1) {
+                    if !budget.spend() {
+                        return current.len();
+                    }
+                    let mut candidate = steps.clone();
+                    let turns = match free_turns(&mut candidate, last, actions.len()) {
+                        Some(turns) => turns,
+                        None => continue,
+                    };
+                    for (iturn, action) in turns.iter().zip(actions.iter()) {
+                        candidate[*iturn] = *action;
+                    }
+                    if simulate(params, node, candidate.clone()) != Destiny::Victory {
+                        continue;
+                    }
+                    let remaining = threats(params, node, candidate.clone());
+                    if remaining.len() < current.len() {
+                        *steps = candidate;
+                        current = remaining;
+                        continue 'improve;
+                    }
+                }
+            }
+        }
+        break;
+    }
+    current.len()
+}
+
+/// Looks for a plan that gets indy to the exit, checking each one
+/// against the rocks until one survives. Plans that new rocks could
+/// ruin are only taken if no safer one shows up, and then `defuse`d.
+/// Plans that stop short of the exit can't be judged by their threats
+/// yet, so they are taken as soon as they survive the rocks.
+pub fn solve<D: Dims>(
+    params: &Params<D>,
+    node: &Node<D>,
+    budget: &mut Budget,
+) -> Result<Plan<D>, SolveError> {
+    let mut rock_node = *node;
+    let mut rock_budget = *budget;
+    let mut rock_tt = TransTable::new();
+    let mut rejected = None;
+    let mut safest: Option<(VecDeque<Action<D>>, usize)> = None;
+    let mut threatened = 0;
+    let mut safe = false;
+    let result = deepen(params, node, budget, &mut |steps, horizon| {
+        let plan = steps.clone();
+        let ok = rock_solve(
+            params,
+            &mut rock_node,
+            steps,
+            horizon,
+            MAX_DERAILS,
+            &mut rock_budget,
+            &mut rock_tt,
+        );
+        if !ok {
+            *steps = plan.clone();
+            rejected = Some((plan, horizon));
+            return false;
+        }
+        if horizon != usize::MAX {
+            // Taking a partial plan ends the round; the next one
+            // starts over with the complete plans it finds
+            safest = None;
+            threatened = 0;
+            safe = false;
+            return true;
+        }
+        let num_threats = threats(params, node, steps.clone()).len();
+        if num_threats == 0 {
+            safe = true;
+            return true;
+        }
+        if safest.as_ref().is_none_or(|(_, n)| num_threats < *n) {
+            safest = Some((steps.clone(), num_threats));
+        }
+        threatened += 1;
+        if threatened < MAX_THREATENED {
+            // The search goes on from the steps it applied, without
+            // the derails
+            *steps = plan;
+            return false;
+        }
+        true
+    });
+    match (result, safest) {
+        (Ok((steps, horizon)), _) if safe => Ok(Plan::new(params, node, steps, horizon)),
+        (_, Some((mut steps, _))) => {
+            if simulate(params, node, steps.clone()) == Destiny::Victory {
+                defuse(params, node, &mut steps, budget);
+            }
+            Ok(Plan::new(params, node, steps, usize::MAX))
+        }
+        (Ok((steps, horizon)), None) => Ok(Plan::new(params, node, steps, horizon)),
+        (Err(SolveError::NoPath), None) => match rejected {
+            Some((steps, horizon)) => Err(unsolved(params, node, steps, horizon)),
+            None => Err(SolveError::NoPath),
+        },
+        (Err(e), None) => Err(e),
+    }
+}
+
+pub fn main() -> Result<(), Box<dyn Error>> {
+    run(solve)
+}
@@ -19,6 +19,8 @@ pub enum Error {
     CellParseError,
     LineIteratorEnded,
     InvalidInput,
+    GridTooLarge(u16, u16),
+    TooManyRocks(usize),
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
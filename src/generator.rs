@@ -0,0 +1,206 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Random level generator
+//!
+//! Carves a path from the top row to the exit with rooms that connect
+//! to each other, fills the rest of the grid, adds decoy branches,
+//! scrambles the rooms that can be rotated, freezes some and adds
+//! rocks. Only the levels that the oracle wins are returned. All the
+//! randomness comes from a `StdRng` seeded by the caller, so the same
+//! seed always gives the same level.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use super::core::*;
+use super::level::Level;
+use super::oracle::oracle;
+
+/// Number of levels tried by `generate` before giving up
+pub const MAX_ATTEMPTS: usize = 100;
+
+/// States explored by the oracle when checking a level; levels it
+/// can't decide within that are discarded
+pub const CHECK_STATES: usize = 50_000;
+
+/// How hard the generated levels are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    /// Chance that each rotatable room of the path starts out of place
+    pub scramble: f64,
+    /// Chance that each room in place is frozen
+    pub frozen: f64,
+    /// Number of decoy branches off the path
+    pub decoys: usize,
+    /// Rocks already in the grid in the first turn
+    pub rocks: usize,
+    /// Rocks that show up in later turns
+    pub spawns: usize,
+}
+
+impl Difficulty {
+    /// Levels in the style of each episode of the game: the path is
+    /// already open in episode 1, has to be opened in episode 2, and
+    /// has rocks showing up along the way in episode 3.
+    pub fn episode(episode: usize) -> Difficulty {
+        match episode {
+            1 => Difficulty {
+                scramble: 0.0,
+                frozen: 1.0,
+                decoys: 1,
+                rocks: 0,
+                spawns: 0,
+            },
+            2 => Difficulty {
+                scramble: 0.5,
+                frozen: 0.3,
+                decoys: 2,
+                rocks: 1,
+                spawns: 0,
+            },
+            _ => Difficulty {
+                scramble: 0.6,
+                frozen: 0.2,
+                decoys: 3,
+                rocks: 1,
+                spawns: 2,
+            },
+        }
+    }
+}
+
+/// Generates a winnable level of the given size
+///
+/// Returns `None` if the size doesn't fit `D` or if no winnable level
+/// shows up in `MAX_ATTEMPTS` tries, which gets likelier as levels get
+/// larger than the oracle can handle.
+pub fn generate<D: Dims>(
+    seed: u64,
+    width: u16,
+    height: u16,
+    difficulty: &Difficulty,
+) -> Option<Level<D>> {
+    if width == 0 || height == 0 || width > D::Qa::WIDTH || height > D::Qa::HEIGHT {
+        return None;
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..MAX_ATTEMPTS)
+        .map(|_| candidate(&mut rng, width, height, difficulty))
+        .find(winnable)
+}
+
+/// A room that connects the entry direction to the exit direction
+fn connect(rng: &mut StdRng, entry: Qr, exit: Qr) -> Cell {
+    let cells = Cell::ALL
+        .into_iter()
+        .filter(|cell| cell.enter(&entry) == Some(exit))
+        .collect::<Vec<_>>();
+    *cells.choose(rng).expect("no cell connects the directions")
+}
+
+/// Carves a path from the top row to the bottom one; returns the
+/// rooms with their entry and exit directions.
+fn carve<D: Dims>(rng: &mut StdRng, width: u16, height: u16) -> Vec<(D::Qa, Qr, Qr)> {
+    let mut path = vec![];
+    let mut x = rng.gen_range(0..width);
+    for y in 0..height {
+        let mut entry = Qr::S;
+        let target = rng.gen_range(0..width);
+        let dir = if target > x { Qr::E } else { Qr::W };
+        while x != target {
+            path.push((D::Qa::try_from((x, y)).unwrap(), entry, dir));
+            entry = dir;
+            x = if dir == Qr::E { x + 1 } else { x - 1 };
+        }
+        path.push((D::Qa::try_from((x, y)).unwrap(), entry, Qr::S));
+    }
+    path
+}
+
+/// A random walk through the free rooms, starting next to the path
+fn decoy<D: Dims>(rng: &mut StdRng, grid: &mut D::Grid, taken: &mut D::Gridbool, start: D::Qa) {
+    let mut entry = *[Qr::E, Qr::W].choose(rng).unwrap();
+    let mut qa = match start + entry {
+        Some(qa) => qa,
+        None => return,
+    };
+    while !taken[qa] {
+        let exit = if entry == Qr::S {
+            *[Qr::S, Qr::E, Qr::W].choose(rng).unwrap()
+        } else {
+            *[Qr::S, entry].choose(rng).unwrap()
+        };
+        grid[qa] = connect(rng, entry, exit);
+        taken.set_t(qa);
+        qa = match qa + exit {
+            Some(qa) => qa,
+            None => return,
+        };
+        entry = exit;
+    }
+}
+
+/// Builds a level, not necessarily winnable
+fn candidate<D: Dims>(
+    rng: &mut StdRng,
+    width: u16,
+    height: u16,
+    difficulty: &Difficulty,
+) -> Level<D> {
+    let mut level = Level::<D>::default();
+    let params = &mut level.params;
+    params.width = width;
+    params.height = height;
+    let path = carve::<D>(rng, width, height);
+    for (qa, entry, exit) in &path {
+        params.grid0[*qa] = connect(rng, *entry, *exit);
+    }
+    let onpath = path.iter().map(|(qa, _, _)| *qa).collect::<D::Gridbool>();
+    // The rooms outside the level count as taken, so that they stay empty
+    let inside = |qa: &D::Qa| qa.tuple().0 < width && qa.tuple().1 < height;
+    let mut taken = D::Qa::iter()
+        .filter(|qa| onpath[*qa] || !inside(qa))
+        .collect::<D::Gridbool>();
+    for _ in 0..difficulty.decoys {
+        let (start, _, _) = *path.choose(rng).unwrap();
+        decoy::<D>(rng, &mut params.grid0, &mut taken, start);
+    }
+    for qa in D::Qa::iter().filter(|qa| !taken[*qa]) {
+        params.grid0[qa] = *Cell::ALL.choose(rng).unwrap();
+    }
+    let mut frozen = vec![];
+    for qa in D::Qa::iter().filter(inside) {
+        let cell = params.grid0[qa];
+        if cell.num_rot() > 0 && onpath[qa] && rng.gen_bool(difficulty.scramble) {
+            let n = rng.gen_range(1..=cell.num_rot());
+            params.grid0[qa] = cell.rotate_n(&Rotation::Right, n);
+        } else if rng.gen_bool(difficulty.frozen) {
+            frozen.push(qa);
+        }
+    }
+    params.frozen = frozen.into_iter().collect();
+    params.exit = path.last().unwrap().0;
+    level.node.set_grid(params.grid0);
+    level.node.indy = Entity {
+        qa: path[0].0,
+        qr: Qr::S,
+    };
+    let spawns = rock_spawns(&level.params, &level.node);
+    for rock in spawns.choose_multiple(rng, difficulty.rocks) {
+        level.node.add_rock(*rock);
+    }
+    for rock in spawns.choose_multiple(rng, difficulty.spawns) {
+        let turn = rng.gen_range(1..=path.len());
+        level.rocks.push((turn, *rock));
+    }
+    level.rocks.sort_by_key(|(turn, _)| *turn);
+    level
+}
+
+/// Whether the oracle wins the level within `CHECK_STATES`
+pub fn winnable<D: Dims>(level: &Level<D>) -> bool {
+    oracle(level, CHECK_STATES).is_ok()
+}
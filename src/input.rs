@@ -15,9 +15,7 @@ const DEBUG: bool = false;
 impl FromStr for Cell {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let firstchar = s.chars().next().unwrap();
-        let s = if firstchar == '-' { &s[1..] } else { s };
-        match s {
+        match s.strip_prefix('-').unwrap_or(s) {
             "0" => Ok(Cell::Type0),
             "1" => Ok(Cell::Type1),
             "2" => Ok(Cell::Type2),
@@ -37,13 +35,16 @@ impl FromStr for Cell {
     }
 }
 
-impl FromStr for Entity {
+impl<D: Dims> FromStr for Entity<D> {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let v = s.split(' ').collect::<Vec<_>>();
+        let [x, y, pos] = v[..] else {
+            return Err(Error::InvalidInput);
+        };
         Ok(Entity {
-            qa: (v[0].parse::<u16>()?, v[1].parse::<u16>()?).try_into()?,
-            qr: match v[2] {
+            qa: (x.parse::<u16>()?, y.parse::<u16>()?).try_into()?,
+            qr: match pos {
                 "TOP" => Qr::S,
                 "LEFT" => Qr::E,
                 "RIGHT" => Qr::W,
@@ -55,6 +56,27 @@ impl FromStr for Entity {
     }
 }
 
+impl<D: Dims> FromStr for Action<D> {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v = s.split_whitespace().collect::<Vec<_>>();
+        match v[..] {
+            ["WAIT", ..] => Ok(Action::Wait),
+            [x, y, rot, ..] => Ok(Action::new(
+                (x.parse::<u16>()?, y.parse::<u16>()?).try_into()?,
+                match rot {
+                    "LEFT" => Rotation::Left,
+                    "RIGHT" => Rotation::Right,
+                    _ => {
+                        return Err(Error::InvalidInput);
+                    }
+                },
+            )),
+            _ => Err(Error::InvalidInput),
+        }
+    }
+}
+
 fn lineread(lineit: &mut impl Iterator<Item = io::Result<String>>) -> Result<String, Error> {
     let line = lineit.next().ok_or(Error::LineIteratorEnded)??;
     if DEBUG {
@@ -63,61 +85,76 @@ fn lineread(lineit: &mut impl Iterator<Item = io::Result<String>>) -> Result<Str
     Ok(line)
 }
 
-pub fn input_first(
+pub fn input_first<D: Dims>(
     lineit: &mut impl Iterator<Item = io::Result<String>>,
-    params: &mut Params,
-    node: &mut Node,
+    params: &mut Params<D>,
+    node: &mut Node<D>,
 ) -> Result<(), Error> {
     let line = lineread(lineit)?;
     let wh = line.split(' ').collect::<Vec<_>>();
-    params.width = wh[0].parse()?;
-    params.height = wh[1].parse()?;
+    let [width, height] = wh[..] else {
+        return Err(Error::InvalidInput);
+    };
+    params.width = width.parse()?;
+    params.height = height.parse()?;
+    if params.width == 0 || params.height == 0 {
+        return Err(Error::InvalidInput);
+    }
+    if params.width > D::Qa::WIDTH || params.height > D::Qa::HEIGHT {
+        return Err(Error::GridTooLarge(params.width, params.height));
+    }
     for (y, lineres) in (0..params.height).zip(lineit.take(params.height as usize)) {
         let line = lineres?;
         if DEBUG {
             eprintln!("# {}", line);
         }
-        let gridline = params.grid0.line_mut(y);
-        for (x, cellnum) in line.split(' ').enumerate() {
-            let firstchar = cellnum.chars().next().unwrap();
-            if firstchar == '-' {
-                let qa = Qa::try_from((x as u16, y))?;
-                params.frozen.set_t(&qa);
+        let cellnums = line.split(' ').collect::<Vec<_>>();
+        if cellnums.len() != params.width as usize {
+            return Err(Error::InvalidInput);
+        }
+        for (x, cellnum) in cellnums.into_iter().enumerate() {
+            let qa = D::Qa::try_from((x as u16, y))?;
+            if cellnum.starts_with('-') {
+                params.frozen.set_t(qa);
             }
-            gridline[x] = cellnum.parse()?;
+            params.grid0[qa] = cellnum.parse()?;
         }
     }
-    node.grid = params.grid0;
+    node.set_grid(params.grid0);
     let line = lineread(lineit)?;
-    params.exit = Qa::try_from((line.parse()?, params.height - 1))?;
+    params.exit = D::Qa::try_from((line.parse()?, params.height - 1))?;
     Ok(())
 }
 
-pub fn input_ep1(
+pub fn input_ep1<D: Dims>(
     lineit: &mut impl Iterator<Item = io::Result<String>>,
-    _params: &Params,
-    node: &mut Node,
+    _params: &Params<D>,
+    node: &mut Node<D>,
 ) -> Result<(), Error> {
     let line = lineread(lineit)?;
     node.indy = line.parse()?;
     Ok(())
 }
 
-pub fn input_ep2(
+/// Reads indy and the rocks, matching each rock to the one it
+/// continues from in `node` (see `Node::track_rocks`); `node.grid`
+/// must already have the rotation played in the previous turn.
+/// Returns the slots of the rocks that just showed up.
+pub fn input_ep2<D: Dims>(
     lineit: &mut impl Iterator<Item = io::Result<String>>,
-    params: &Params,
-    node: &mut Node,
-) -> Result<(), Error> {
+    params: &Params<D>,
+    node: &mut Node<D>,
+) -> Result<Vec<D::IRock>, Error> {
     input_ep1(lineit, params, node)?;
     let line = lineread(lineit)?;
     let num_rocks = line.parse::<usize>()?;
-    for irock in IRock::iter() {
-        if usize::from(irock) < num_rocks {
-            let rock_str = lineread(lineit)?;
-            node.rock[irock] = Some(rock_str.parse()?);
-        } else {
-            node.rock[irock] = None;
-        }
+    if num_rocks > D::IRock::SIZE {
+        return Err(Error::TooManyRocks(num_rocks));
     }
-    Ok(())
+    let mut read = Vec::with_capacity(num_rocks);
+    for _ in 0..num_rocks {
+        read.push(lineread(lineit)?.parse()?);
+    }
+    node.track_rocks(&read)
+        .ok_or(Error::TooManyRocks(num_rocks))
 }
@@ -0,0 +1,151 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Plain-text level files
+//!
+//! A level file starts with the same lines that the game sends on
+//! the first turn, followed by indy's start position and by the
+//! rocks, one per line:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! W H
+//! H lines with W cell types each; a `-` prefix marks a frozen cell
+//! EX
+//! XI YI POSI
+//! TURN XR YR POSR
+//! ```
+//!
+//! `POS` is the side through which the entity entered the room:
+//! `TOP`, `LEFT` or `RIGHT`, as in the game input. `TURN` is the
+//! turn in which the rock shows up, starting at 0; rocks of turn 0
+//! are already in `Node::rock`.
+//!
+//! When saving, the cells are taken from `Node::grid`, so that a
+//! level can also be saved in the middle of a game.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use super::core::*;
+use super::error::*;
+use super::input::*;
+
+#[derive(Debug, Default)]
+pub struct Level<D: Dims = Dims20> {
+    pub params: Params<D>,
+    pub node: Node<D>,
+    pub rocks: Vec<(usize, Entity<D>)>,
+}
+
+impl<D: Dims> Level<D> {
+    pub fn load(lineit: &mut impl Iterator<Item = io::Result<String>>) -> Result<Level<D>, Error> {
+        let mut lineit = lineit.filter(|line| match line {
+            Ok(line) => !line.is_empty() && !line.starts_with('#'),
+            Err(_) => true,
+        });
+        let mut level = Level::<D>::default();
+        input_first(&mut lineit, &mut level.params, &mut level.node)?;
+        input_ep1(&mut lineit, &level.params, &mut level.node)?;
+        for line in lineit {
+            let line = line?;
+            let (turn, rock) = line.split_once(' ').ok_or(Error::InvalidInput)?;
+            level.rocks.push((turn.parse()?, rock.parse()?));
+        }
+        let mut node = level.node;
+        level.add_rocks(&mut node, 0)?;
+        level.node = node;
+        level.rocks.retain(|(turn, _)| *turn > 0);
+        // Positions were only checked against the grid of `D`, which
+        // can be larger than the level
+        let params = &level.params;
+        let inside = |qa: D::Qa| {
+            let (x, y) = qa.tuple();
+            x < params.width && y < params.height
+        };
+        if !inside(params.exit)
+            || !inside(level.node.indy.qa)
+            || !level.node.rock.iter().flatten().all(|rock| inside(rock.qa))
+            || !level.rocks.iter().all(|(_, rock)| inside(rock.qa))
+        {
+            return Err(Error::InvalidInput);
+        }
+        Ok(level)
+    }
+
+    pub fn save(&self, w: &mut impl io::Write) -> io::Result<()> {
+        write!(w, "{}", self)
+    }
+
+    /// Adds the rocks that show up in the given turn to the node
+    pub fn add_rocks(&self, node: &mut Node<D>, turn: usize) -> Result<(), Error> {
+        let num_rocks = node.rock.iter().flatten().count() + self.rocks_at(turn).count();
+        for rock in self.rocks_at(turn) {
+            node.add_rock(*rock).ok_or(Error::TooManyRocks(num_rocks))?;
+        }
+        Ok(())
+    }
+
+    /// The rocks that show up in the given turn
+    pub fn rocks_at(&self, turn: usize) -> impl Iterator<Item = &Entity<D>> {
+        self.rocks
+            .iter()
+            .filter(move |(t, _)| *t == turn)
+            .map(|(_, rock)| rock)
+    }
+}
+
+impl<D: Dims> FromStr for Level<D> {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Level::load(&mut s.lines().map(String::from).map(Ok))
+    }
+}
+
+impl<D: Dims> fmt::Display for Level<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in params_lines(&self.params, &self.node.grid) {
+            writeln!(f, "{}", line)?;
+        }
+        writeln!(f, "{}", entity_str(&self.node.indy))?;
+        for rock in self.node.rock.iter().flatten() {
+            writeln!(f, "0 {}", entity_str(rock))?;
+        }
+        for (turn, rock) in &self.rocks {
+            writeln!(f, "{} {}", turn, entity_str(rock))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats an entity as in the game input: `X Y POS`
+pub fn entity_str<D: Dims>(entity: &Entity<D>) -> String {
+    let t = entity.qa.tuple();
+    let pos = match entity.qr {
+        Qr::E => "LEFT",
+        Qr::W => "RIGHT",
+        _ => "TOP",
+    };
+    format!("{} {} {}", t.0, t.1, pos)
+}
+
+/// The lines that the game sends on the first turn, with the cells
+/// taken from `grid`
+pub fn params_lines<D: Dims>(params: &Params<D>, grid: &D::Grid) -> Vec<String> {
+    let mut lines = vec![format!("{} {}", params.width, params.height)];
+    for y in 0..params.height {
+        let line = (0..params.width)
+            .map(|x| {
+                let qa = D::Qa::try_from((x, y)).unwrap();
+                let frozen = if params.frozen[qa] { "-" } else { "" };
+                format!("{}{}", frozen, grid[qa] as u8)
+            })
+            .collect::<Vec<_>>();
+        lines.push(line.join(" "));
+    }
+    lines.push(format!("{}", params.exit.tuple().0));
+    lines
+}
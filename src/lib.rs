@@ -2,14 +2,25 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
+#[allow(clippy::all, ambiguous_glob_reexports)]
 pub mod sqrid;
+#[allow(ambiguous_glob_reexports)]
 pub use self::sqrid::*;
 
+#[allow(clippy::all)]
 pub mod andex;
+#[allow(ambiguous_glob_reexports)]
 pub use self::andex::*;
 
 pub mod core;
+pub mod difftest;
 pub mod entrypoint1;
 pub mod entrypoint2;
+pub mod entrypoint3;
 pub mod error;
+pub mod generator;
 pub mod input;
+pub mod level;
+pub mod minimize;
+pub mod oracle;
+pub mod referee;
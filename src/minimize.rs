@@ -0,0 +1,226 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Delta-debugging minimiser for failing levels
+//!
+//! Takes a level and a predicate that tells whether a level still
+//! fails, and keeps simplifying the level while the predicate holds:
+//! crops the edges of the grid, drops rocks, blanks rooms to
+//! `Cell::Type0` and unfreezes rooms. The simplifications are tried
+//! in chunks that get halved when they don't work, as in ddmin, so
+//! that large levels don't need one predicate call per room.
+
+use super::core::*;
+use super::level::Level;
+
+/// A level as plain data, easy to change and turn back into a level
+#[derive(Debug, Clone, PartialEq)]
+struct Draft {
+    width: u16,
+    height: u16,
+    /// Cell and frozen flag of each room, row by row
+    rooms: Vec<(Cell, bool)>,
+    exit: u16,
+    indy: (u16, u16, Qr),
+    /// Rocks with the turn they show up in, 0 for the initial ones
+    rocks: Vec<(usize, (u16, u16, Qr))>,
+}
+
+fn entity_tuple<D: Dims>(entity: &Entity<D>) -> (u16, u16, Qr) {
+    let t = entity.qa.tuple();
+    (t.0, t.1, entity.qr)
+}
+
+fn tuple_entity<D: Dims>(t: (u16, u16, Qr)) -> Option<Entity<D>> {
+    Some(Entity {
+        qa: D::Qa::try_from((t.0, t.1)).ok()?,
+        qr: t.2,
+    })
+}
+
+impl Draft {
+    fn new<D: Dims>(level: &Level<D>) -> Draft {
+        let params = &level.params;
+        let mut rooms = vec![];
+        for y in 0..params.height {
+            for x in 0..params.width {
+                let qa = D::Qa::try_from((x, y)).unwrap();
+                rooms.push((level.node.grid[qa], params.frozen[qa]));
+            }
+        }
+        let initial = level.node.rock.iter().flatten().map(|rock| (0, rock));
+        let later = level.rocks.iter().map(|(turn, rock)| (*turn, rock));
+        Draft {
+            width: params.width,
+            height: params.height,
+            rooms,
+            exit: params.exit.tuple().0,
+            indy: entity_tuple(&level.node.indy),
+            rocks: initial
+                .chain(later)
+                .map(|(turn, rock)| (turn, entity_tuple(rock)))
+                .collect(),
+        }
+    }
+
+    fn level<D: Dims>(&self) -> Option<Level<D>> {
+        let mut level = Level::<D>::default();
+        let params = &mut level.params;
+        params.width = self.width;
+        params.height = self.height;
+        let mut frozen = vec![];
+        for (i, (cell, is_frozen)) in self.rooms.iter().enumerate() {
+            let x = i as u16 % self.width;
+            let y = i as u16 / self.width;
+            let qa = D::Qa::try_from((x, y)).ok()?;
+            params.grid0[qa] = *cell;
+            if *is_frozen {
+                frozen.push(qa);
+            }
+        }
+        params.frozen = frozen.into_iter().collect();
+        params.exit = D::Qa::try_from((self.exit, self.height - 1)).ok()?;
+        level.node.set_grid(params.grid0);
+        level.node.indy = tuple_entity(self.indy)?;
+        for (turn, rock) in &self.rocks {
+            let rock = tuple_entity(*rock)?;
+            if *turn == 0 {
+                level.node.add_rock(rock)?;
+            } else {
+                level.rocks.push((*turn, rock));
+            }
+        }
+        Some(level)
+    }
+
+    /// Removes an edge of the grid: 0 is the top row, 1 the bottom
+    /// row, 2 the left column and 3 the right column. Rocks on the edge
+    /// are dropped; edges with Indy or with the exit column are kept.
+    fn crop(&self, edge: usize) -> Option<Draft> {
+        let (width, height) = (self.width, self.height);
+        let (dx, dy, keep): (u16, u16, &dyn Fn(u16, u16) -> bool) = match edge {
+            0 if height > 1 => (0, 1, &|_, y| y > 0),
+            1 if height > 1 => (0, 0, &|_, y| y < height - 1),
+            2 if width > 1 && self.exit > 0 => (1, 0, &|x, _| x > 0),
+            3 if width > 1 && self.exit < width - 1 => (0, 0, &|x, _| x < width - 1),
+            _ => return None,
+        };
+        if !keep(self.indy.0, self.indy.1) {
+            return None;
+        }
+        let shift = |t: (u16, u16, Qr)| (t.0 - dx, t.1 - dy, t.2);
+        let rooms = self
+            .rooms
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| keep(*i as u16 % width, *i as u16 / width))
+            .map(|(_, room)| *room)
+            .collect();
+        Some(Draft {
+            width: width - if edge >= 2 { 1 } else { 0 },
+            height: height - if edge < 2 { 1 } else { 0 },
+            rooms,
+            exit: self.exit - dx,
+            indy: shift(self.indy),
+            rocks: self
+                .rocks
+                .iter()
+                .filter(|(_, rock)| keep(rock.0, rock.1))
+                .map(|(turn, rock)| (*turn, shift(*rock)))
+                .collect(),
+        })
+    }
+}
+
+/// Applies `change` to chunks of `items`, keeping the changes after
+/// which the level still fails; the chunks are halved until they
+/// have a single item. Returns whether anything changed.
+fn reduce<D: Dims, T: Copy + PartialEq>(
+    draft: &mut Draft,
+    mut items: Vec<T>,
+    change: impl Fn(&Draft, &[T]) -> Draft,
+    failing: &mut impl FnMut(&Level<D>) -> bool,
+) -> bool {
+    let mut changed = false;
+    let mut size = items.len().div_ceil(2);
+    while size > 0 {
+        let mut start = 0;
+        while start < items.len() {
+            let end = (start + size).min(items.len());
+            let candidate = change(draft, &items[start..end]);
+            if candidate.level().is_some_and(|level| failing(&level)) {
+                *draft = candidate;
+                items.drain(start..end);
+                changed = true;
+            } else {
+                start = end;
+            }
+        }
+        size = if size == 1 { 0 } else { size.div_ceil(2) };
+    }
+    changed
+}
+
+/// Returns the smallest level found that still fails, according to
+/// `failing`, starting from `level`, which must fail.
+pub fn minimize<D: Dims>(level: &Level<D>, mut failing: impl FnMut(&Level<D>) -> bool) -> Level<D> {
+    let mut draft = Draft::new(level);
+    loop {
+        let mut changed = false;
+        for edge in 0..4 {
+            while let Some(candidate) = draft.crop(edge) {
+                if !candidate.level().is_some_and(|level| failing(&level)) {
+                    break;
+                }
+                draft = candidate;
+                changed = true;
+            }
+        }
+        let rocks = draft.rocks.clone();
+        changed |= reduce(
+            &mut draft,
+            rocks,
+            |draft, rocks| {
+                let mut draft = draft.clone();
+                draft.rocks.retain(|rock| !rocks.contains(rock));
+                draft
+            },
+            &mut failing,
+        );
+        let rooms = (0..draft.rooms.len())
+            .filter(|i| draft.rooms[*i].0 != Cell::Type0)
+            .collect();
+        changed |= reduce(
+            &mut draft,
+            rooms,
+            |draft, irooms| {
+                let mut draft = draft.clone();
+                for i in irooms {
+                    draft.rooms[*i].0 = Cell::Type0;
+                }
+                draft
+            },
+            &mut failing,
+        );
+        let frozen = (0..draft.rooms.len())
+            .filter(|i| draft.rooms[*i].1)
+            .collect();
+        changed |= reduce(
+            &mut draft,
+            frozen,
+            |draft, irooms| {
+                let mut draft = draft.clone();
+                for i in irooms {
+                    draft.rooms[*i].1 = false;
+                }
+                draft
+            },
+            &mut failing,
+        );
+        if !changed {
+            break;
+        }
+    }
+    draft.level().expect("the minimised level is valid")
+}
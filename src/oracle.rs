@@ -0,0 +1,135 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Exhaustive solver for small levels
+//!
+//! Goes through every game state that can be reached from the start
+//! of a level, rocks that show up later included, so that it can tell
+//! whether the level can be won at all and find a winning plan with
+//! as few rotations as possible. It's the reference the heuristic
+//! solvers are tested against; the limit on the number of states makes
+//! it give up cleanly on levels that are too large.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use super::core::*;
+use super::entrypoint2::{Plan, SolveError};
+use super::level::Level;
+
+/// Default limit on the number of states explored by `oracle`
+pub const MAX_STATES: usize = 1_000_000;
+
+/// A state at the start of a turn, after the rocks of the turn show
+/// up, with the action that led to it
+struct State<D: Dims> {
+    node: Node<D>,
+    turn: usize,
+    parent: usize,
+    action: Action<D>,
+}
+
+/// The actions that can be played in the node: `Wait` and every
+/// legal rotation that changes the grid
+fn actions<D: Dims>(params: &Params<D>, node: &Node<D>) -> Vec<Action<D>> {
+    let mut actions = vec![Action::Wait];
+    for qa in D::Qa::iter() {
+        let action = Action::new(qa, Rotation::Left);
+        if !action.is_valid(params, node) {
+            continue;
+        }
+        actions.push(action);
+        // Both rotations give the same cell in groups of 2
+        if node.grid[qa].num_rot() > 1 {
+            actions.push(Action::new(qa, Rotation::Right));
+        }
+    }
+    actions
+}
+
+/// Finds a plan that wins the level with the fewest rotations,
+/// exploring up to `max_states` states
+///
+/// Returns `SolveError::NoPath` if the level can't be won, and
+/// `SolveError::BudgetExceeded` if that can't be decided within
+/// `max_states`.
+pub fn oracle<D: Dims>(level: &Level<D>, max_states: usize) -> Result<Plan<D>, SolveError> {
+    let params = &level.params;
+    // After the last rock shows up, the turn doesn't matter anymore
+    let last_turn = level.rocks.iter().map(|(turn, _)| *turn).max().unwrap_or(0);
+    let key = |node: &Node<D>, turn: usize| (*node, turn.min(last_turn));
+    let mut states = vec![State {
+        node: level.node,
+        turn: 0,
+        parent: usize::MAX,
+        action: Action::Wait,
+    }];
+    // Fewest rotations to get to each state, and the state's index
+    let mut best = HashMap::from([(key(&level.node, 0), (0, 0))]);
+    // 0-1 BFS: rotations go to the back, waits to the front
+    let mut queue = VecDeque::from([(0, 0)]);
+    while let Some((istate, cost)) = queue.pop_front() {
+        let state = &states[istate];
+        if best[&key(&state.node, state.turn)] != (cost, istate) {
+            continue;
+        }
+        if state.node.indy.qa == params.exit {
+            return Ok(plan(&states, istate));
+        }
+        let (node0, turn) = (state.node, state.turn);
+        for action in actions(params, &node0) {
+            let mut node = node0;
+            node.apply(&action);
+            if !node.eval_all_step() || node.has_rock_collision().is_some() {
+                continue;
+            }
+            if node.indy.qa != params.exit
+                && level
+                    .rocks_at(turn + 1)
+                    .any(|rock| node.add_rock(*rock).is_none())
+            {
+                continue;
+            }
+            let next_cost = cost + usize::from(action != Action::Wait);
+            let next_key = key(&node, turn + 1);
+            if best
+                .get(&next_key)
+                .is_some_and(|(best_cost, _)| *best_cost <= next_cost)
+            {
+                continue;
+            }
+            if states.len() >= max_states {
+                return Err(SolveError::BudgetExceeded);
+            }
+            best.insert(next_key, (next_cost, states.len()));
+            if next_cost == cost {
+                queue.push_front((states.len(), next_cost));
+            } else {
+                queue.push_back((states.len(), next_cost));
+            }
+            states.push(State {
+                node,
+                turn: turn + 1,
+                parent: istate,
+                action,
+            });
+        }
+    }
+    Err(SolveError::NoPath)
+}
+
+/// Collects the actions that lead to the state
+fn plan<D: Dims>(states: &[State<D>], mut istate: usize) -> Plan<D> {
+    let mut steps = VecDeque::new();
+    while states[istate].parent != usize::MAX {
+        steps.push_front(states[istate].action);
+        istate = states[istate].parent;
+    }
+    Plan {
+        turns: Some(steps.len()),
+        rotations: steps.iter().filter(|a| **a != Action::Wait).count(),
+        destiny: Destiny::Victory,
+        steps,
+    }
+}
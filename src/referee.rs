@@ -0,0 +1,260 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use super::core::*;
+use super::error::*;
+use super::level::*;
+
+/// Maximum number of turns before the referee gives up on the bot
+pub const MAX_TURNS: usize = 500;
+
+/// How long the referee waits for each bot command
+pub const TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/* Outcome **********************************************************/
+
+#[derive(Debug)]
+pub enum Outcome<D: Dims = Dims20> {
+    Victory(usize),
+    Defeat {
+        turn: usize,
+        destiny: Destiny<D>,
+        action: Action<D>,
+    },
+    BadOutput {
+        turn: usize,
+        line: String,
+    },
+    Timeout(usize),
+    TurnLimit,
+}
+
+impl<D: Dims> fmt::Display for Outcome<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Victory(turns) => write!(f, "victory in {} turns", turns),
+            Outcome::Defeat {
+                turn,
+                destiny,
+                action,
+            } => write!(
+                f,
+                "defeat at turn {}: {:?} after \"{}\"",
+                turn, destiny, action
+            ),
+            Outcome::BadOutput { turn, line } => {
+                write!(f, "defeat at turn {}: bad output \"{}\"", turn, line)
+            }
+            Outcome::Timeout(turn) => write!(f, "defeat at turn {}: timeout", turn),
+            Outcome::TurnLimit => write!(f, "defeat: turn limit {} reached", MAX_TURNS),
+        }
+    }
+}
+
+/* Bot **************************************************************/
+
+/// A bot process that talks the game protocol through stdin/stdout
+#[derive(Debug)]
+pub struct Bot {
+    child: process::Child,
+    stdin: process::ChildStdin,
+    stdout: mpsc::Receiver<String>,
+}
+
+impl Bot {
+    pub fn spawn(command: &str, args: &[String]) -> Result<Bot, Error> {
+        let mut child = process::Command::new(command)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(Error::InvalidInput)?;
+        let lines = io::BufReader::new(child.stdout.take().ok_or(Error::InvalidInput)?).lines();
+        let (sender, stdout) = mpsc::channel();
+        thread::spawn(move || {
+            for line in lines.map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Bot {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    pub fn send(&mut self, line: &str) -> Result<(), Error> {
+        writeln!(self.stdin, "{}", line)?;
+        Ok(())
+    }
+
+    /// Returns the next line, or `None` on timeout
+    pub fn recv(&mut self) -> Result<Option<String>, Error> {
+        self.stdin.flush()?;
+        match self.stdout.recv_timeout(TIMEOUT) {
+            Ok(line) => Ok(Some(line.trim_end().to_string())),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(Error::LineIteratorEnded),
+        }
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/* Game *************************************************************/
+
+/// A level being played, turn by turn, by the rules of the game
+#[derive(Debug)]
+pub struct Game<'a, D: Dims = Dims20> {
+    pub level: &'a Level<D>,
+    /// The state at the start of the current turn, with the rocks
+    /// that show up in it
+    pub node: Node<D>,
+    pub turn: usize,
+}
+
+impl<'a, D: Dims> Game<'a, D> {
+    pub fn new(level: &'a Level<D>) -> Result<Game<'a, D>, Error> {
+        let mut node = level.node;
+        level.add_rocks(&mut node, 0)?;
+        Ok(Game {
+            level,
+            node,
+            turn: 0,
+        })
+    }
+
+    /// Plays the action and moves indy and the rocks; returns the
+    /// outcome if the game is over, otherwise starts the next turn.
+    pub fn play(&mut self, action: Action<D>) -> Result<Option<Outcome<D>>, Error> {
+        let params = &self.level.params;
+        let node = &mut self.node;
+        let defeat = |destiny| {
+            Some(Outcome::Defeat {
+                turn: self.turn,
+                destiny,
+                action,
+            })
+        };
+        if node.try_apply(params, &action).is_err() {
+            return Ok(defeat(Destiny::InvalidAction));
+        }
+        if !node.eval_all_step() {
+            return Ok(defeat(Destiny::Wall(node.indy.qa)));
+        }
+        if let Some(irock) = node.has_rock_collision() {
+            return Ok(defeat(Destiny::Rock(irock, node.indy.qa)));
+        }
+        if node.indy.qa == params.exit {
+            return Ok(Some(Outcome::Victory(self.turn + 1)));
+        }
+        self.turn += 1;
+        if self.turn == MAX_TURNS {
+            return Ok(Some(Outcome::TurnLimit));
+        }
+        self.level.add_rocks(node, self.turn)?;
+        Ok(None)
+    }
+}
+
+fn send_first<D: Dims>(bot: &mut Bot, params: &Params<D>) -> Result<(), Error> {
+    for line in params_lines(params, &params.grid0) {
+        bot.send(&line)?;
+    }
+    Ok(())
+}
+
+fn send_turn<D: Dims>(bot: &mut Bot, node: &Node<D>, episode: usize) -> Result<(), Error> {
+    bot.send(&entity_str(&node.indy))?;
+    if episode > 1 {
+        let rocks = node.rock.iter().flatten().collect::<Vec<_>>();
+        bot.send(&format!("{}", rocks.len()))?;
+        for rock in rocks {
+            bot.send(&entity_str(rock))?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses the bot command; in episode 1 that is indy's next room,
+/// which must match the prediction.
+fn parse_action<D: Dims>(line: &str, node: &Node<D>, episode: usize) -> Option<Action<D>> {
+    if episode > 1 {
+        return line.parse().ok();
+    }
+    let next = node.indy.step(node)?.qa.tuple();
+    let v = line.split_whitespace().collect::<Vec<_>>();
+    match v[..] {
+        [x, y, ..] if (x.parse().ok()?, y.parse().ok()?) == next => Some(Action::Wait),
+        _ => None,
+    }
+}
+
+/// Plays the level against the bot until indy reaches the exit or
+/// dies.
+pub fn play<D: Dims>(level: &Level<D>, bot: &mut Bot, episode: usize) -> Result<Outcome<D>, Error> {
+    let mut game = Game::new(level)?;
+    send_first(bot, &level.params)?;
+    loop {
+        send_turn(bot, &game.node, episode)?;
+        let line = match bot.recv() {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(Outcome::Timeout(game.turn)),
+            Err(Error::LineIteratorEnded) => String::new(),
+            Err(e) => return Err(e),
+        };
+        let action = match parse_action(&line, &game.node, episode) {
+            Some(action) => action,
+            None => {
+                return Ok(Outcome::BadOutput {
+                    turn: game.turn,
+                    line,
+                })
+            }
+        };
+        if let Some(outcome) = game.play(action)? {
+            return Ok(outcome);
+        }
+    }
+}
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let episode = if args.first().map(String::as_str) == Some("--ep1") {
+        args.remove(0);
+        1
+    } else {
+        2
+    };
+    if args.len() < 2 {
+        eprintln!("usage: referee [--ep1] LEVEL COMMAND [ARGS...]");
+        process::exit(2);
+    }
+    let level = fs::read_to_string(&args[0])?.parse::<Level>()?;
+    let mut bot = Bot::spawn(&args[1], &args[2..])?;
+    let outcome = play(&level, &mut bot, episode)?;
+    println!("{}", outcome);
+    if !matches!(outcome, Outcome::Victory(_)) {
+        process::exit(1);
+    }
+    Ok(())
+}
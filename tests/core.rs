@@ -0,0 +1,321 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::level::*;
+
+fn irock(i: usize) -> IRock {
+    IRock::try_from(i).unwrap()
+}
+
+#[test]
+fn test_rock_crash() -> Result<()> {
+    let level =
+        "3 3\n2 8 2\n3 3 0\n3 0 0\n0\n0 1 TOP\n0 0 0 LEFT\n0 2 0 RIGHT\n".parse::<Level>()?;
+    let mut node = level.node;
+    let ends = node.eval_rocks_step();
+    let qa = Qa::try_from((1, 0))?;
+    assert_eq!(ends[irock(0)], Some(RockEnd::Crash(irock(1), qa)));
+    assert_eq!(ends[irock(1)], Some(RockEnd::Crash(irock(0), qa)));
+    assert!(node.rock.iter().all(Option::is_none));
+    Ok(())
+}
+
+#[test]
+fn test_rock_wall() -> Result<()> {
+    let level = "3 3\n2 3 2\n3 3 0\n3 0 0\n0\n0 1 TOP\n0 0 0 LEFT\n0 1 1 TOP\n".parse::<Level>()?;
+    let mut node = level.node;
+    let ends = node.eval_rocks_step();
+    assert_eq!(ends[irock(0)], Some(RockEnd::Wall(Qa::try_from((1, 0))?)));
+    assert_eq!(ends[irock(1)], Some(RockEnd::Wall(Qa::try_from((1, 2))?)));
+    assert!(node.rock.iter().all(Option::is_none));
+    Ok(())
+}
+
+#[test]
+fn test_rock_moves() -> Result<()> {
+    let level = "3 3\n2 2 2\n3 3 0\n3 0 0\n0\n0 1 TOP\n0 0 0 LEFT\n".parse::<Level>()?;
+    let mut node = level.node;
+    let ends = node.eval_rocks_step();
+    assert!(ends.iter().all(Option::is_none));
+    assert_eq!(node.rock[irock(0)].unwrap().qa, Qa::try_from((1, 0))?);
+    Ok(())
+}
+
+#[test]
+fn test_simulate_events() -> Result<()> {
+    let level = "3 4\n2 8 2\n3 3 0\n3 0 0\n3 0 0\n0\n0 1 TOP\n0 0 0 LEFT\n0 2 0 RIGHT\n"
+        .parse::<Level>()?;
+    let mut events = vec![];
+    let destiny = simulate_events(
+        &level.params,
+        &level.node,
+        VecDeque::new(),
+        usize::MAX,
+        |e| events.push(e),
+    );
+    assert_eq!(destiny, Destiny::Victory);
+    let qa = Qa::try_from((1, 0))?;
+    assert_eq!(
+        events,
+        vec![
+            RockEvent {
+                turn: 0,
+                irock: irock(0),
+                end: RockEnd::Crash(irock(1), qa)
+            },
+            RockEvent {
+                turn: 0,
+                irock: irock(1),
+                end: RockEnd::Crash(irock(0), qa)
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_zobrist() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    let qa = Qa::try_from((1, 0))?;
+    assert_eq!(level.node.grid[qa], Cell::Type12);
+    let mut node1 = level.node;
+    node1.apply(&Action::new(qa, Rotation::Left));
+    node1.apply(&Action::new(qa, Rotation::Left));
+    assert_ne!(node1.zobrist, level.node.zobrist);
+    assert_eq!(node1.zobrist, grid_zobrist::<Dims20>(&node1.grid));
+    let mut node2 = level.node;
+    node2.apply(&Action::new(qa, Rotation::Right));
+    node2.apply(&Action::new(qa, Rotation::Right));
+    assert_eq!(node1, node2);
+    assert_eq!(node1.zobrist, node2.zobrist);
+    let mut set = HashSet::new();
+    set.insert(node1);
+    assert!(set.contains(&node2));
+    assert!(!set.contains(&level.node));
+    Ok(())
+}
+
+#[test]
+fn test_undo() -> Result<()> {
+    let level =
+        "3 3\n2 8 2\n3 10 0\n3 0 0\n0\n0 1 TOP\n0 0 0 LEFT\n0 2 0 RIGHT\n".parse::<Level>()?;
+    let node0 = level.node;
+    let mut node = node0;
+    let qa = Qa::try_from((1, 1))?;
+    let undo1 = node.apply(&Action::new(qa, Rotation::Left));
+    let undo2 = node.apply(&Action::new(qa, Rotation::Left));
+    let undo3 = node.step_undo();
+    assert!(node.eval_all_step());
+    assert!(node.rock.iter().all(Option::is_none));
+    assert_ne!(node, node0);
+    node.undo(undo3);
+    node.undo(undo2);
+    node.undo(undo1);
+    assert_eq!(node, node0);
+    assert_eq!(node.apply(&Action::Wait), Undo::Nothing);
+    assert_eq!(node, node0);
+    // The simulation runs on the node and undoes its changes
+    let steps = VecDeque::from([Action::new(qa, Rotation::Left)]);
+    let (destiny, _) = simulate_mut(&level.params, &mut node, &steps, usize::MAX, |_| {});
+    assert_eq!(destiny, simulate(&level.params, &node0, steps));
+    assert_eq!(node, node0);
+    Ok(())
+}
+
+#[test]
+fn test_cell_groups() {
+    for cell in Cell::ALL {
+        let group = cell.info().group;
+        assert!(group.contains(&cell));
+        assert_eq!(cell.num_rot() + 1, group.len());
+        assert_eq!(cell.rot_cw().rot_cc(), cell);
+        assert_eq!(cell.rot_cc().rot_cw(), cell);
+        let mut rotated = cell;
+        for other in group.iter().cycle().skip_while(|c| **c != cell).skip(1) {
+            rotated = rotated.rot_cw();
+            assert_eq!(rotated, *other);
+            assert_eq!(rotated.info().group, group);
+            if rotated == cell {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cell_entries_for_exit() {
+    let dirs = [Qr::N, Qr::E, Qr::S, Qr::W];
+    for cell in Cell::ALL {
+        for entry in dirs {
+            for exit in dirs {
+                assert_eq!(
+                    cell.enter(&entry) == Some(exit),
+                    cell.entries_for_exit(&exit).any(|e| e == entry),
+                    "{:?} {:?} {:?}",
+                    cell,
+                    entry,
+                    exit
+                );
+            }
+            assert_ne!(cell.enter(&entry), Some(Qr::N));
+        }
+    }
+    assert_eq!(
+        Cell::Type1.entries_for_exit(&Qr::S).collect::<Vec<_>>(),
+        vec![Qr::S, Qr::E, Qr::W]
+    );
+    assert_eq!(
+        Cell::Type5.entries_for_exit(&Qr::S).collect::<Vec<_>>(),
+        vec![Qr::E]
+    );
+    assert_eq!(Cell::Type10.entries_for_exit(&Qr::S).count(), 0);
+}
+
+#[test]
+fn test_cell_rotations_to() {
+    for cell in Cell::ALL {
+        let orientations = cell.orientations().collect::<Vec<_>>();
+        assert_eq!(orientations[0], cell);
+        assert_eq!(orientations.len(), cell.num_rot() + 1);
+        for target in Cell::ALL {
+            match cell.rotations_to(&target) {
+                Some(rots) => {
+                    assert!(orientations.contains(&target));
+                    assert!(rots.len() <= 2);
+                    let rotated = rots.iter().fold(cell, |c, rot| c.rotate(rot));
+                    assert_eq!(rotated, target);
+                }
+                None => assert!(!orientations.contains(&target)),
+            }
+        }
+    }
+    assert_eq!(
+        Cell::Type6.rotations_to(&Cell::Type9),
+        Some(vec![Rotation::Left])
+    );
+    assert_eq!(
+        Cell::Type10.rotations_to(&Cell::Type12),
+        Some(vec![Rotation::Left, Rotation::Left])
+    );
+    assert_eq!(Cell::Type2.rotations_to(&Cell::Type2), Some(vec![]));
+    assert_eq!(Cell::Type2.rotations_to(&Cell::Type4), None);
+}
+
+#[test]
+fn test_cell_rotate_n() {
+    assert_eq!(Cell::Type6.rotate_n(&Rotation::Right, 3), Cell::Type9);
+    assert_eq!(Cell::Type6.rotate_n(&Rotation::Left, 3), Cell::Type7);
+    assert_eq!(Cell::Type11.rotate_n(&Rotation::Left, 6), Cell::Type13);
+    assert_eq!(Cell::Type4.rotate_n(&Rotation::Right, 5), Cell::Type5);
+    assert_eq!(Cell::Type1.rotate_n(&Rotation::Left, 1), Cell::Type1);
+}
+
+#[test]
+fn test_reach() -> Result<()> {
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    let reach = Reach::new(&level.params);
+    assert!(!reach.reaches(&level.node.indy));
+    assert_eq!(
+        reach.overlay().iter_t().collect::<Vec<_>>(),
+        vec![level.params.exit]
+    );
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    assert!(Reach::new(&level.params).reaches(&level.node.indy));
+    Ok(())
+}
+
+#[test]
+fn test_reach_frozen() -> Result<()> {
+    let qa = Qa::try_from((0, 1))?;
+    let level = "1 3\n3\n2\n3\n0\n0 0 TOP\n".parse::<Level>()?;
+    let reach = Reach::new(&level.params);
+    assert!(reach.contains(qa, Qr::S));
+    assert!(reach.reaches(&level.node.indy));
+    let level = "1 3\n3\n-2\n3\n0\n0 0 TOP\n".parse::<Level>()?;
+    let reach = Reach::new(&level.params);
+    assert!(!reach.contains(qa, Qr::S));
+    assert!(!reach.reaches(&level.node.indy));
+    Ok(())
+}
+
+#[test]
+fn test_try_apply() -> Result<()> {
+    let level = "3 3\n-2 8 2\n3 10 1\n3 0 0\n0\n0 1 TOP\n0 1 0 LEFT\n".parse::<Level>()?;
+    let params = &level.params;
+    let mut node = level.node;
+    let rotate =
+        |x, y| -> Result<Action> { Ok(Action::new(Qa::try_from((x, y))?, Rotation::Left)) };
+    let qa = |x, y| Qa::try_from((x, y)).unwrap();
+    assert_eq!(
+        node.try_apply(params, &rotate(5, 0)?),
+        Err(IllegalAction::OutOfBounds(qa(5, 0)))
+    );
+    assert_eq!(
+        node.try_apply(params, &rotate(0, 0)?),
+        Err(IllegalAction::Frozen(qa(0, 0)))
+    );
+    assert_eq!(
+        node.try_apply(params, &rotate(2, 1)?),
+        Err(IllegalAction::NotRotatable(qa(2, 1), Cell::Type1))
+    );
+    assert_eq!(
+        node.try_apply(params, &rotate(0, 1)?),
+        Err(IllegalAction::Indy(qa(0, 1)))
+    );
+    assert_eq!(
+        node.try_apply(params, &rotate(1, 0)?),
+        Err(IllegalAction::Rock(qa(1, 0), irock(0)))
+    );
+    assert_eq!(node, level.node);
+    assert!(node.try_apply(params, &rotate(1, 1)?).is_ok());
+    assert_eq!(node.grid[qa(1, 1)], Cell::Type13);
+    assert!(node.try_apply(params, &Action::Wait).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_track_rocks() -> Result<()> {
+    let level =
+        "3 3\n2 2 2\n2 2 2\n3 3 3\n0\n0 2 TOP\n0 0 0 LEFT\n0 2 1 RIGHT\n".parse::<Level>()?;
+    let mut node = level.node;
+    let read = ["1 1 RIGHT", "1 0 LEFT", "0 1 LEFT"]
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<Vec<Entity>, _>>()?;
+    assert_eq!(node.track_rocks(&read), Some(vec![irock(2)]));
+    assert_eq!(node.rock[irock(0)], Some(read[1]));
+    assert_eq!(node.rock[irock(1)], Some(read[0]));
+    assert_eq!(node.rock[irock(2)], Some(read[2]));
+    // Rocks that are not read anymore free their slots
+    let rock = "1 1 LEFT".parse::<Entity>()?;
+    assert_eq!(node.track_rocks(&[rock]), Some(vec![]));
+    assert_eq!(node.rock[irock(2)], Some(rock));
+    assert_eq!(node.rock.iter().flatten().count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_threats() -> Result<()> {
+    let level = include_str!("levels/spawn.txt").parse::<Level>()?;
+    let rock = "2 3 RIGHT".parse::<Entity>()?;
+    let threat = threats(&level.params, &level.node, VecDeque::new())
+        .into_iter()
+        .find(|threat| threat.rock == rock)
+        .unwrap();
+    assert_eq!(threat.turn, 1);
+    assert!(matches!(threat.destiny, Destiny::Rock(..)));
+    assert!(threat.node.rock.iter().flatten().any(|r| *r == rock));
+    // Rotating (1,3) right away shuts the rock out
+    let steps = VecDeque::from([Action::new(Qa::try_from((1, 3))?, Rotation::Right)]);
+    let threats = threats(&level.params, &level.node, steps);
+    assert!(threats.iter().all(|threat| threat.rock != rock));
+    Ok(())
+}
@@ -0,0 +1,130 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::difftest::*;
+use the_fall::entrypoint2;
+use the_fall::entrypoint3;
+use the_fall::generator::*;
+use the_fall::level::*;
+use the_fall::oracle::MAX_STATES;
+use the_fall::referee::Outcome;
+
+const LEVELS: [&str; 6] = [
+    include_str!("levels/corner.txt"),
+    include_str!("levels/dead.txt"),
+    include_str!("levels/prerotate.txt"),
+    include_str!("levels/rock.txt"),
+    include_str!("levels/rocks2.txt"),
+    include_str!("levels/spawn.txt"),
+];
+
+/// The level and the ones with a single room rotated
+fn variants(level: Level) -> Vec<Level> {
+    let mut levels = vec![];
+    for qa in Qa::iter() {
+        let t = qa.tuple();
+        if t.0 >= level.params.width
+            || t.1 >= level.params.height
+            || level.params.frozen[qa]
+            || level.node.grid[qa].num_rot() == 0
+            || level.node.indy.qa == qa
+        {
+            continue;
+        }
+        let mut variant = level.to_string().parse::<Level>().unwrap();
+        variant.params.grid0[qa] = variant.params.grid0[qa].rotate(&Rotation::Right);
+        variant.node.set_grid(variant.params.grid0);
+        levels.push(variant);
+    }
+    levels.push(level);
+    levels
+}
+
+#[test]
+fn test_difftest() -> Result<()> {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missed");
+    let mut missed = vec![];
+    for level_str in LEVELS {
+        for level in variants(level_str.parse()?) {
+            let report = difftest(
+                &level,
+                Duration::from_secs(1),
+                MAX_STATES,
+                entrypoint2::solve,
+            )?;
+            if report.missed() {
+                missed.push(save_level(&level, &dir)?);
+            }
+        }
+    }
+    assert!(missed.is_empty(), "levels missed: {:?}", missed);
+    Ok(())
+}
+
+#[test]
+fn test_report() -> Result<()> {
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    let report = difftest(
+        &level,
+        Duration::from_secs(1),
+        MAX_STATES,
+        entrypoint2::solve,
+    )?;
+    assert!(matches!(report.outcome, Outcome::Defeat { .. }));
+    assert!(report.oracle.is_err());
+    assert!(!report.missed());
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("saved");
+    let path = save_level(&level, &dir)?;
+    assert_eq!(save_level(&level, &dir)?, path);
+    let saved = std::fs::read_to_string(path)?.parse::<Level>()?;
+    assert_eq!(saved.to_string(), level.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_spawns() -> Result<()> {
+    // spawn.txt with (0,2) rotated: the oracle has the time to fix it
+    // and rotate (1,3) before the rock shows up, but the solver
+    // doesn't know about the rock yet
+    let level = "3 6\n3 0 0\n3 0 0\n2 0 0\n7 2 2\n3 0 0\n3 0 0\n0\n0 0 TOP\n1 2 3 RIGHT\n"
+        .parse::<Level>()?;
+    let report = difftest(
+        &level,
+        Duration::from_secs(1),
+        MAX_STATES,
+        entrypoint2::solve,
+    )?;
+    assert!(matches!(report.outcome, Outcome::Defeat { .. }));
+    assert!(report.oracle.is_ok());
+    assert!(matches!(report.known, Some(Err(_))));
+    assert!(!report.missed());
+    Ok(())
+}
+
+#[test]
+fn test_generated() -> Result<()> {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missed");
+    let turn_time = Duration::from_millis(100);
+    let mut missed = vec![];
+    for seed in 0..30 {
+        let level = generate::<Dims20>(seed, 4, 3, &Difficulty::episode(2)).unwrap();
+        let report = difftest(&level, turn_time, CHECK_STATES, entrypoint2::solve)?;
+        if report.missed() {
+            missed.push(save_level(&level, &dir)?);
+        }
+        let level = generate::<Dims20>(seed, 4, 3, &Difficulty::episode(3)).unwrap();
+        let report = difftest(&level, turn_time, CHECK_STATES, entrypoint3::solve)?;
+        if report.missed() {
+            missed.push(save_level(&level, &dir)?);
+        }
+    }
+    assert!(missed.is_empty(), "levels missed: {:?}", missed);
+    Ok(())
+}
@@ -28,7 +28,7 @@ fn test_input() -> Result<()> {
         "2",
     ];
     let mut it_lines = vec_lines.iter().cloned().map(String::from).map(Ok);
-    let mut params = Params::default();
+    let mut params = Params::<Dims20>::default();
     let mut node = Node::default();
     input_first(&mut it_lines, &mut params, &mut node)?;
     let inputs = vec![
@@ -63,7 +63,7 @@ fn test_input() -> Result<()> {
         ("2 8 RIGHT", Qa::try_from((2, 9)).unwrap()),
     ];
     for i in &inputs {
-        input_ep1(&mut iter::once(Ok(i.0.to_string())), &mut params, &mut node)?;
+        input_ep1(&mut iter::once(Ok(i.0.to_string())), &params, &mut node)?;
         assert_eq!(eval(&params, &node), i.1);
     }
     Ok(())
@@ -2,11 +2,272 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::Duration;
+
 use anyhow::Result;
 
 use the_fall::core::*;
 use the_fall::entrypoint2::*;
 use the_fall::input::*;
+use the_fall::level::*;
+use the_fall::referee::{Game, Outcome};
+
+the_fall::dims_create!(Dims2x24, 2, 24);
+the_fall::dims_create!(Dims12x3, 12, 3, 16);
+
+fn budget() -> Budget {
+    Budget::new(Duration::from_secs(10))
+}
+
+fn play(level_str: &str) -> Result<usize> {
+    let level = level_str.parse::<Level>()?;
+    let mut game = Game::new(&level)?;
+    loop {
+        let action = solve(&level.params, &game.node, &mut budget())?.action();
+        match game.play(action)? {
+            None => {}
+            Some(Outcome::Victory(turns)) => return Ok(turns),
+            Some(outcome) => panic!("{}", outcome),
+        }
+    }
+}
+
+#[test]
+fn test_prerotate() -> Result<()> {
+    assert_eq!(play(include_str!("levels/prerotate.txt"))?, 6);
+    Ok(())
+}
+
+#[test]
+fn test_rocks2() -> Result<()> {
+    assert_eq!(play(include_str!("levels/rocks2.txt"))?, 5);
+    Ok(())
+}
+
+#[test]
+fn test_rockroom() -> Result<()> {
+    play(include_str!("levels/rockroom.txt"))?;
+    Ok(())
+}
+
+#[test]
+fn test_bring_forward() -> Result<()> {
+    // The rotation of (4,2) that indy needs on turn 3 derails the rock
+    // if it's done on turn 1 instead
+    let level = "5 5\n9 8 10 1 -3\n-9 3 9 5 -1\n6 5 2 3 4\n4 6 -12 3 10\n8 6 5 6 6\n0\n1 1 TOP\n0 4 0 TOP\n"
+        .parse::<Level>()?;
+    let plan = solve(&level.params, &level.node, &mut budget())?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    Ok(())
+}
+
+#[test]
+fn test_trade_turns() -> Result<()> {
+    // The rock is in (3,1) when the path needs it rotated; the rotation
+    // has to trade turns with the one of (2,1)
+    let level = "4 4\n5 10 -13 12\n0 5 7 11\n8 6 -6 10\n4 2 3 1\n3\n0 0 TOP\n0 3 0 RIGHT\n"
+        .parse::<Level>()?;
+    let plan = solve(&level.params, &level.node, &mut budget())?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    Ok(())
+}
+
+#[test]
+fn test_half_turn() -> Result<()> {
+    // (3,1) needs a half turn; turning it left lets the rock in, and
+    // then it can't be finished, while turning it right destroys the
+    // rock
+    let level = "4 3\n12 4 5 1\n0 11 1 12\n1 4 7 12\n2\n2 0 TOP\n0 3 0 TOP\n".parse::<Level>()?;
+    let plan = solve(&level.params, &level.node, &mut budget())?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    assert_eq!(
+        plan.steps[0],
+        Action::new(Qa::try_from((3, 1))?, Rotation::Right)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_invalid_action() -> Result<()> {
+    // (0,1) has to be rotated right away, but the rock is in it
+    let level = "1 3\n-3\n2\n-3\n0\n0 0 TOP\n0 0 1 LEFT\n".parse::<Level>()?;
+    assert_eq!(
+        solve(&level.params, &level.node, &mut budget()),
+        Err(SolveError::InvalidAction)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_plan() -> Result<()> {
+    let level = include_str!("levels/rocks2.txt").parse::<Level>()?;
+    let plan = solve(&level.params, &level.node, &mut budget())?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    assert_eq!(plan.turns, Some(5));
+    assert_eq!(plan.rotations, 2);
+    assert_eq!(
+        simulate(&level.params, &level.node, plan.steps.clone()),
+        Destiny::Victory
+    );
+    Ok(())
+}
+
+#[test]
+fn test_plan_cache() -> Result<()> {
+    let level = include_str!("levels/rocks2.txt").parse::<Level>()?;
+    let params = &level.params;
+    let mut node = level.node;
+    let mut cache = PlanCache::default();
+    let mut solves = 0;
+    let mut turns = 0;
+    while node.indy.qa != params.exit {
+        cache.turn(params, &mut node, &mut budget(), |params, node, budget| {
+            solves += 1;
+            solve(params, node, budget)
+        });
+        assert!(node.eval_all_step());
+        assert_eq!(node.has_rock_collision(), None);
+        turns += 1;
+    }
+    assert_eq!(turns, 5);
+    assert_eq!(solves, 1);
+    Ok(())
+}
+
+#[test]
+fn test_plan_cache_desync() -> Result<()> {
+    let level = include_str!("levels/rocks2.txt").parse::<Level>()?;
+    let params = &level.params;
+    let mut node = level.node;
+    let mut cache = PlanCache::default();
+    cache.turn(params, &mut node, &mut budget(), solve);
+    assert!(node.eval_all_step());
+    assert!(cache.plan.is_some());
+    let mut read = node;
+    read.rock = Default::default();
+    assert!(!cache.sync(params, &read));
+    assert!(cache.plan.is_none());
+    assert!(cache.sync(params, &read));
+    Ok(())
+}
+
+#[test]
+fn test_budget_exceeded() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    let mut budget = budget();
+    budget.nodes = 1;
+    assert_eq!(
+        solve(&level.params, &level.node, &mut budget),
+        Err(SolveError::BudgetExceeded)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rock_solve_depth() -> Result<()> {
+    let level = include_str!("levels/rocks2.txt").parse::<Level>()?;
+    let mut budget = budget();
+    let mut tt = TransTable::new();
+    let mut steps = VecDeque::new();
+    let mut node = level.node;
+    assert!(!rock_solve(
+        &level.params,
+        &mut node,
+        &mut steps,
+        usize::MAX,
+        1,
+        &mut budget,
+        &mut tt,
+    ));
+    // The failure is in the transposition table now:
+    let nodes = budget.nodes;
+    assert!(!rock_solve(
+        &level.params,
+        &mut node,
+        &mut steps,
+        usize::MAX,
+        1,
+        &mut budget,
+        &mut tt,
+    ));
+    assert_eq!(budget.nodes, nodes);
+    assert!(rock_solve(
+        &level.params,
+        &mut node,
+        &mut steps,
+        usize::MAX,
+        2,
+        &mut budget,
+        &mut tt,
+    ));
+    assert_eq!(node, level.node);
+    assert_eq!(steps.iter().filter(|a| **a != Action::Wait).count(), 2);
+    assert_eq!(
+        simulate(&level.params, &level.node, steps),
+        Destiny::Victory
+    );
+    Ok(())
+}
+
+#[test]
+fn test_deepen_anytime() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    let mut budget = budget();
+    budget.nodes = 20;
+    let (steps, horizon) =
+        deepen(&level.params, &level.node, &mut budget, &mut |_, _| true).expect("no partial plan");
+    assert!(budget.is_over());
+    assert_eq!(horizon, HORIZON0);
+    assert_eq!(
+        simulate_horizon(&level.params, &level.node, steps, horizon),
+        Destiny::Alive
+    );
+    Ok(())
+}
+
+#[test]
+fn test_deepen_complete() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    let (steps, horizon) =
+        deepen(&level.params, &level.node, &mut budget(), &mut |_, _| true).expect("no plan");
+    assert_eq!(horizon, usize::MAX);
+    assert_eq!(
+        simulate(&level.params, &level.node, steps),
+        Destiny::Victory
+    );
+    Ok(())
+}
+
+#[test]
+fn test_checked_apply() -> Result<()> {
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    let mut node = level.node;
+    let action = Action::new(node.indy.qa, Rotation::Left);
+    assert_eq!(
+        checked_apply(&level.params, &mut node, action),
+        Action::Wait
+    );
+    assert_eq!(node, level.node);
+    let action = Action::new(Qa::try_from((0, 1))?, Rotation::Left);
+    assert_eq!(checked_apply(&level.params, &mut node, action), action);
+    assert_ne!(node, level.node);
+    Ok(())
+}
+
+#[test]
+fn test_fallback() -> Result<()> {
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    assert_eq!(
+        solve(&level.params, &level.node, &mut budget()),
+        Err(SolveError::NoPath)
+    );
+    let action = fallback(&level.params, &level.node);
+    assert!(action.is_valid(&level.params, &level.node));
+    assert_eq!(action.qa(), Some(Qa::try_from((0, 1))?));
+    Ok(())
+}
 
 #[test]
 fn test_case4() -> Result<()> {
@@ -25,7 +286,7 @@ fn test_case4() -> Result<()> {
         "2",
     ];
     let mut it_lines = vec_lines.iter().cloned().map(String::from).map(Ok);
-    let mut params = Params::default();
+    let mut params = Params::<Dims20>::default();
     let mut node = Node::default();
     input_first(&mut it_lines, &mut params, &mut node)?;
     let inputs = vec![
@@ -68,14 +329,58 @@ fn test_case4() -> Result<()> {
         vec!["2 8 LEFT", "0"],
     ];
     for i in &inputs {
-        input_ep2(
-            &mut i.into_iter().map(|s| Ok(s.to_string())),
-            &mut params,
-            &mut node,
-        )?;
-        let actionopt = solve(&params, &node);
-        assert!(actionopt.is_some());
-        node.apply(&actionopt.unwrap());
+        input_ep2(&mut i.iter().map(|s| Ok(s.to_string())), &params, &mut node)?;
+        let plan = solve(&params, &node, &mut budget())?;
+        assert_eq!(plan.destiny, Destiny::Victory);
+        node.apply(&plan.action());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_tall_level() -> Result<()> {
+    // A level taller than the game's 20 rows, with a room to rotate
+    let mut level_str = String::from("2 22\n");
+    for y in 0..22 {
+        level_str.push_str(if y == 11 { "2 0\n" } else { "3 0\n" });
     }
+    level_str.push_str("0\n0 0 TOP\n");
+    let level = level_str.parse::<Level<Dims2x24>>()?;
+    let plan = solve(&level.params, &level.node, &mut budget())?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    assert_eq!(plan.turns, Some(21));
+    assert_eq!(plan.rotations, 1);
+    Ok(())
+}
+
+fn many_rocks_lines() -> impl Iterator<Item = std::io::Result<String>> {
+    let mut lines = vec!["12 3".to_string()];
+    lines.extend((0..3).map(|_| ["3"; 12].join(" ")));
+    lines.push("0".to_string());
+    lines.push("0 0 TOP".to_string());
+    lines.push("11".to_string());
+    lines.extend((1..12).map(|x| format!("{} 0 TOP", x)));
+    lines.into_iter().map(Ok)
+}
+
+#[test]
+fn test_many_rocks() -> Result<()> {
+    let mut it_lines = many_rocks_lines();
+    let mut params = Params::<Dims20>::default();
+    let mut node = Node::default();
+    input_first(&mut it_lines, &mut params, &mut node)?;
+    assert!(matches!(
+        input_ep2(&mut it_lines, &params, &mut node),
+        Err(the_fall::error::Error::TooManyRocks(11))
+    ));
+    // With room for more rocks, each one keeps its slot as it moves
+    let mut it_lines = many_rocks_lines();
+    let mut params = Params::<Dims12x3>::default();
+    let mut node = Node::default();
+    input_first(&mut it_lines, &mut params, &mut node)?;
+    input_ep2(&mut it_lines, &params, &mut node)?;
+    node.eval_rocks_step();
+    let rocks = node.rock.iter().flatten().map(|rock| rock.qa.tuple());
+    assert!(rocks.eq((1..12).map(|x| (x, 1))));
     Ok(())
 }
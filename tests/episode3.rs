@@ -0,0 +1,130 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use the_fall::core::{threats, Destiny, Dims20};
+use the_fall::entrypoint2::{self, Budget, PlanCache, SolveError};
+use the_fall::entrypoint3::*;
+use the_fall::generator::*;
+use the_fall::level::*;
+use the_fall::referee::{Game, Outcome};
+
+fn play(level_str: &str) -> Result<usize> {
+    let level = level_str.parse::<Level>()?;
+    let mut game = Game::new(&level)?;
+    loop {
+        let action = solve(
+            &level.params,
+            &game.node,
+            &mut Budget::new(Duration::from_secs(10)),
+        )?
+        .action();
+        match game.play(action)? {
+            None => {}
+            Some(Outcome::Victory(turns)) => return Ok(turns),
+            Some(outcome) => panic!("{}", outcome),
+        }
+    }
+}
+
+#[test]
+fn test_prerotate() -> Result<()> {
+    assert_eq!(play(include_str!("levels/prerotate.txt"))?, 6);
+    Ok(())
+}
+
+#[test]
+fn test_rock() -> Result<()> {
+    assert_eq!(play(include_str!("levels/rock.txt"))?, 4);
+    Ok(())
+}
+
+#[test]
+fn test_rocks2() -> Result<()> {
+    assert_eq!(play(include_str!("levels/rocks2.txt"))?, 5);
+    Ok(())
+}
+
+#[test]
+fn test_case4() -> Result<()> {
+    play(include_str!("levels/case4.txt"))?;
+    Ok(())
+}
+
+#[test]
+fn test_unavoidable_rock() -> Result<()> {
+    let level = "2 3\n-3 0\n-1 -2\n-3 0\n0\n0 0 TOP\n0 1 1 RIGHT\n".parse::<Level>()?;
+    let result = solve(
+        &level.params,
+        &level.node,
+        &mut Budget::new(Duration::from_secs(10)),
+    );
+    assert_eq!(result, Err(SolveError::UnavoidableRock(0.try_into()?)));
+    Ok(())
+}
+
+#[test]
+fn test_contingency() -> Result<()> {
+    let level = include_str!("levels/spawn.txt").parse::<Level>()?;
+    let params = &level.params;
+    let mut node = level.node;
+    let mut cache = PlanCache {
+        contingencies: true,
+        ..Default::default()
+    };
+    let mut solves = vec![];
+    let mut turn = 0;
+    while node.indy.qa != params.exit {
+        for rock in level.rocks_at(turn) {
+            node.add_rock(*rock);
+        }
+        let mut budget = Budget::new(Duration::from_secs(10));
+        // Unlike `solve`, the episode 2 solver doesn't defuse the rock
+        // of turn 1, leaving it to the branches
+        cache.turn(params, &mut node, &mut budget, |params, node, budget| {
+            solves.push(turn);
+            entrypoint2::solve(params, node, budget)
+        });
+        if turn == 0 {
+            assert!(!cache.branches.is_empty());
+        }
+        assert!(node.eval_all_step());
+        assert_eq!(node.has_rock_collision(), None);
+        turn += 1;
+    }
+    assert!(solves.iter().all(|t| *t == 0));
+    Ok(())
+}
+
+#[test]
+fn test_threat_free() -> Result<()> {
+    let level = include_str!("levels/spawn.txt").parse::<Level>()?;
+    let plan = solve(
+        &level.params,
+        &level.node,
+        &mut Budget::new(Duration::from_secs(10)),
+    )?;
+    assert_eq!(plan.destiny, Destiny::Victory);
+    assert!(threats(&level.params, &level.node, plan.steps).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_complete_plan() {
+    // The first round finds threatened plans that stop short of the
+    // exit; they must not be preferred to the complete plans found later
+    for seed in 0..5 {
+        let level = generate::<Dims20>(seed, 6, 6, &Difficulty::episode(3)).unwrap();
+        let plan = solve(
+            &level.params,
+            &level.node,
+            &mut Budget::new(Duration::from_secs(10)),
+        )
+        .unwrap();
+        assert_eq!(plan.destiny, Destiny::Victory, "{}", level);
+    }
+}
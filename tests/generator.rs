@@ -0,0 +1,65 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::generator::*;
+use the_fall::level::*;
+use the_fall::oracle::*;
+
+the_fall::dims_create!(Dims4, 4, 4);
+
+#[test]
+fn test_seed() {
+    let difficulty = Difficulty::episode(3);
+    let level1 = generate::<Dims20>(7, 6, 4, &difficulty).unwrap();
+    let level2 = generate::<Dims20>(7, 6, 4, &difficulty).unwrap();
+    assert_eq!(level1.to_string(), level2.to_string());
+}
+
+#[test]
+fn test_winnable() {
+    for episode in 1..=3 {
+        let difficulty = Difficulty::episode(episode);
+        for seed in 0..5 {
+            let level = generate::<Dims20>(seed, 4, 4, &difficulty).unwrap();
+            assert_eq!((level.params.width, level.params.height), (4, 4));
+            assert!(oracle(&level, MAX_STATES).is_ok(), "{}", level);
+        }
+    }
+}
+
+#[test]
+fn test_episodes() {
+    for seed in 0..5 {
+        // Nothing to rotate in episode 1: the path is open and frozen
+        let level = generate::<Dims20>(seed, 4, 4, &Difficulty::episode(1)).unwrap();
+        assert_eq!(oracle(&level, MAX_STATES).unwrap().rotations, 0);
+        assert!(level.node.rock.iter().all(Option::is_none));
+        assert!(level.rocks.is_empty());
+        let level = generate::<Dims20>(seed, 4, 4, &Difficulty::episode(3)).unwrap();
+        let rocks = level.node.rock.iter().flatten().count() + level.rocks.len();
+        assert!(rocks > 0, "{}", level);
+        assert!(level.rocks.iter().all(|(turn, _)| *turn > 0));
+    }
+}
+
+#[test]
+fn test_roundtrip() -> Result<()> {
+    let level = generate::<Dims20>(3, 6, 4, &Difficulty::episode(3)).unwrap();
+    let parsed = level.to_string().parse::<Level>()?;
+    assert_eq!(parsed.to_string(), level.to_string());
+    assert_eq!(parsed.node, level.node);
+    Ok(())
+}
+
+#[test]
+fn test_size() {
+    let difficulty = Difficulty::episode(2);
+    assert!(generate::<Dims4>(0, 5, 4, &difficulty).is_none());
+    assert!(generate::<Dims4>(0, 4, 0, &difficulty).is_none());
+    let level = generate::<Dims4>(0, 4, 4, &difficulty).unwrap();
+    assert!(oracle(&level, MAX_STATES).is_ok());
+}
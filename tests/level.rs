@@ -0,0 +1,127 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::convert::TryFrom;
+use std::mem;
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::error::Error;
+use the_fall::level::*;
+
+the_fall::dims_create!(Dims4, 4, 4);
+the_fall::dims_create!(Dims4Rocks2, 4, 4, 2);
+
+#[test]
+fn test_load() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    assert_eq!((level.params.width, level.params.height), (13, 10));
+    assert_eq!(level.params.exit, Qa::try_from((2, 9))?);
+    assert!(level.params.frozen[Qa::try_from((0, 0))?]);
+    assert!(level.params.frozen[Qa::try_from((8, 2))?]);
+    assert!(!level.params.frozen[Qa::try_from((1, 0))?]);
+    assert_eq!(level.node.grid[Qa::try_from((8, 2))?], Cell::Type9);
+    assert_eq!(level.node.indy.qa, Qa::try_from((0, 0))?);
+    assert_eq!(level.node.indy.qr, Qr::S);
+    assert!(level.rocks.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_rocks() -> Result<()> {
+    let level = "2 2\n3 2\n3 0\n0\n0 0 TOP\n0 1 0 RIGHT\n3 1 0 RIGHT\n".parse::<Level>()?;
+    let rock = level.node.rock.iter().flatten().next().unwrap();
+    assert_eq!((rock.qa, rock.qr), (Qa::try_from((1, 0))?, Qr::W));
+    assert_eq!(level.rocks.len(), 1);
+    assert_eq!(level.rocks_at(3).count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_roundtrip() -> Result<()> {
+    for level_str in [
+        include_str!("levels/case4.txt"),
+        include_str!("levels/rock.txt"),
+        "2 2\n3 2\n3 0\n0\n0 0 TOP\n0 1 0 RIGHT\n3 1 0 RIGHT\n",
+    ] {
+        let level = level_str.parse::<Level>()?;
+        let mut saved = vec![];
+        level.save(&mut saved)?;
+        let saved = String::from_utf8(saved)?;
+        let level2 = saved.parse::<Level>()?;
+        assert_eq!(level2.to_string(), saved);
+        assert_eq!(level2.node.grid, level.node.grid);
+        assert_eq!(level2.params.frozen, level.params.frozen);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_dims() -> Result<()> {
+    let level_str = "2 2\n3 2\n3 0\n0\n0 0 TOP\n0 1 0 RIGHT\n";
+    let level = level_str.parse::<Level<Dims4>>()?;
+    assert_eq!(level.to_string(), level_str);
+    assert!(mem::size_of::<Node<Dims4>>() < mem::size_of::<Node>());
+    Ok(())
+}
+
+#[test]
+fn test_too_large() {
+    let level_str = format!("21 1\n{}\n0\n0 0 TOP\n", ["3"; 21].join(" "));
+    assert!(matches!(
+        level_str.parse::<Level>(),
+        Err(Error::GridTooLarge(21, 1))
+    ));
+    assert!(matches!(
+        "5 1\n3 3 3 3 3\n0\n0 0 TOP\n".parse::<Level<Dims4>>(),
+        Err(Error::GridTooLarge(5, 1))
+    ));
+}
+
+#[test]
+fn test_malformed() {
+    for level_str in [
+        // Rock without a direction
+        "1 1\n3\n0\n0 0 TOP\n3 1\n",
+        // Header without the height
+        "2\n3 3\n0\n0 0 TOP\n",
+        // Empty cell
+        "2 1\n3  3\n0\n0 0 TOP\n",
+        // Too many cells in a row
+        "2 1\n3 3 3\n0\n0 0 TOP\n",
+        // Empty grid
+        "0 0\n0\n0 0 TOP\n",
+        // Indy without a direction
+        "1 1\n3\n0\n0 0\n",
+        // Missing indy
+        "1 1\n3\n0\n",
+        // Exit outside the grid
+        "1 1\n3\n1\n0 0 TOP\n",
+        // Indy outside the grid
+        "1 1\n3\n0\n0 1 TOP\n",
+        // Rocks outside the grid
+        "2 1\n3 3\n0\n0 0 TOP\n0 2 0 TOP\n",
+        "2 1\n3 3\n0\n0 0 TOP\n1 1 1 TOP\n",
+    ] {
+        assert!(
+            matches!(
+                level_str.parse::<Level>(),
+                Err(Error::InvalidInput) | Err(Error::LineIteratorEnded)
+            ),
+            "{:?}",
+            level_str
+        );
+    }
+}
+
+#[test]
+fn test_too_many_rocks() {
+    let level_str = "4 1\n3 3 3 3\n0\n0 0 TOP\n0 1 0 TOP\n0 2 0 TOP\n0 3 0 TOP\n";
+    assert!(level_str.parse::<Level<Dims4>>().is_ok());
+    assert!(matches!(
+        level_str.parse::<Level<Dims4Rocks2>>(),
+        Err(Error::TooManyRocks(3))
+    ));
+}
@@ -0,0 +1,94 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::difftest::play_solver;
+use the_fall::entrypoint2::{Budget, Plan, SolveError};
+use the_fall::generator::*;
+use the_fall::level::*;
+use the_fall::minimize::*;
+use the_fall::oracle::*;
+use the_fall::referee::Outcome;
+
+/// A solver that never rotates anything
+fn wait(_params: &Params, _node: &Node, _budget: &mut Budget) -> Result<Plan, SolveError> {
+    Ok(Plan {
+        steps: VecDeque::from([Action::Wait]),
+        destiny: Destiny::Alive,
+        turns: None,
+        rotations: 0,
+    })
+}
+
+/// Waiting gets indy killed by a rock
+fn crushed(level: &Level) -> bool {
+    matches!(
+        play_solver(level, Duration::ZERO, wait),
+        Ok(Outcome::Defeat {
+            destiny: Destiny::Rock(..),
+            ..
+        })
+    )
+}
+
+/// The level can be won, but not by waiting
+fn missed(level: &Level) -> bool {
+    !matches!(
+        play_solver(level, Duration::ZERO, wait),
+        Ok(Outcome::Victory(_))
+    ) && oracle(level, MAX_STATES).is_ok()
+}
+
+/// Number of rooms that are not empty
+fn rooms(level: &Level) -> usize {
+    Qa::iter()
+        .filter(|qa| level.node.grid[*qa] != Cell::Type0)
+        .count()
+}
+
+#[test]
+fn test_minimize_rock() -> Result<()> {
+    let level = include_str!("levels/rock.txt").parse::<Level>()?;
+    assert!(crushed(&level));
+    let minimized = minimize(&level, crushed);
+    // The rows below the crash go away, the rooms the rock and indy
+    // go through stay
+    assert_eq!(
+        minimized.to_string(),
+        "3 3\n3 0 0\n3 0 0\n7 2 2\n0\n0 0 TOP\n0 2 2 RIGHT\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_minimize_unwinnable() -> Result<()> {
+    let unwinnable = |level: &Level| oracle(level, MAX_STATES) == Err(SolveError::NoPath);
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    let minimized = minimize(&level, unwinnable);
+    assert!(unwinnable(&minimized));
+    assert_eq!(rooms(&minimized), 0);
+    assert!(minimized.params.height < level.params.height);
+    Ok(())
+}
+
+#[test]
+fn test_minimize_generated() -> Result<()> {
+    for seed in 0..3 {
+        let level = generate::<Dims20>(seed, 6, 5, &Difficulty::episode(2)).unwrap();
+        assert!(missed(&level));
+        let minimized = minimize(&level, missed);
+        assert!(missed(&minimized));
+        assert!(rooms(&minimized) < rooms(&level), "{}", minimized);
+        assert!(minimized.params.frozen.iter_t().next().is_none());
+        // Ready to be pasted into a level file
+        let parsed = minimized.to_string().parse::<Level>()?;
+        assert_eq!(parsed.to_string(), minimized.to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use the_fall::core::*;
+use the_fall::entrypoint2::SolveError;
+use the_fall::level::*;
+use the_fall::oracle::*;
+use the_fall::referee::{Game, Outcome};
+
+/// Plays the steps like the referee does, rocks that show up later
+/// included
+fn wins(level: &Level, steps: &VecDeque<Action>) -> Result<bool> {
+    let mut game = Game::new(level)?;
+    for action in steps {
+        if let Some(outcome) = game.play(*action)? {
+            return Ok(matches!(outcome, Outcome::Victory(_)));
+        }
+    }
+    Ok(false)
+}
+
+#[test]
+fn test_oracle() -> Result<()> {
+    for (level_str, rotations) in [
+        (include_str!("levels/corner.txt"), 0),
+        (include_str!("levels/prerotate.txt"), 4),
+        (include_str!("levels/rocks2.txt"), 2),
+        (include_str!("levels/spawn.txt"), 1),
+    ] {
+        let level = level_str.parse::<Level>()?;
+        let plan = oracle(&level, MAX_STATES)?;
+        assert_eq!(plan.destiny, Destiny::Victory);
+        assert_eq!(plan.rotations, rotations);
+        assert_eq!(plan.turns, Some(plan.steps.len()));
+        assert!(wins(&level, &plan.steps)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_oracle_unwinnable() -> Result<()> {
+    let level = include_str!("levels/dead.txt").parse::<Level>()?;
+    assert_eq!(
+        oracle(&level, MAX_STATES).map(|plan| plan.steps),
+        Err(SolveError::NoPath)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_oracle_state_limit() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    assert_eq!(
+        oracle(&level, 1000).map(|plan| plan.steps),
+        Err(SolveError::BudgetExceeded)
+    );
+    Ok(())
+}
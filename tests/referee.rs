@@ -0,0 +1,99 @@
+// Copyright (C) 2021 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+use anyhow::Result;
+
+use the_fall::level::*;
+use the_fall::referee::*;
+
+fn play_bin(level_str: &str, bin: &str, episode: usize) -> Result<Outcome> {
+    let level = level_str.parse::<Level>()?;
+    let mut bot = Bot::spawn(bin, &[])?;
+    Ok(play(&level, &mut bot, episode)?)
+}
+
+#[test]
+fn test_episode1() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/corner.txt"),
+        env!("CARGO_BIN_EXE_episode1"),
+        1,
+    )?;
+    assert!(matches!(outcome, Outcome::Victory(6)), "{}", outcome);
+    Ok(())
+}
+
+#[test]
+fn test_episode2_case4() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/case4.txt"),
+        env!("CARGO_BIN_EXE_episode2"),
+        2,
+    )?;
+    assert!(matches!(outcome, Outcome::Victory(_)), "{}", outcome);
+    Ok(())
+}
+
+#[test]
+fn test_episode3_case4() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/case4.txt"),
+        env!("CARGO_BIN_EXE_episode3"),
+        3,
+    )?;
+    assert!(matches!(outcome, Outcome::Victory(_)), "{}", outcome);
+    Ok(())
+}
+
+#[test]
+fn test_episode3_rock() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/rock.txt"),
+        env!("CARGO_BIN_EXE_episode3"),
+        3,
+    )?;
+    assert!(matches!(outcome, Outcome::Victory(4)), "{}", outcome);
+    Ok(())
+}
+
+#[test]
+fn test_episode2_dead() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/dead.txt"),
+        env!("CARGO_BIN_EXE_episode2"),
+        2,
+    )?;
+    assert!(
+        matches!(outcome, Outcome::Defeat { turn: 3, .. }),
+        "{}",
+        outcome
+    );
+    Ok(())
+}
+
+#[test]
+fn test_bad_output() -> Result<()> {
+    let level = include_str!("levels/case4.txt").parse::<Level>()?;
+    // A bot that answers every line with garbage
+    let script = "while read line; do echo garbage; done".to_string();
+    let mut bot = Bot::spawn("sh", &["-c".to_string(), script])?;
+    let outcome = play(&level, &mut bot, 2)?;
+    assert!(
+        matches!(&outcome, Outcome::BadOutput { turn: 0, line } if line == "garbage"),
+        "{}",
+        outcome
+    );
+    Ok(())
+}
+
+#[test]
+fn test_episode3_spawn() -> Result<()> {
+    let outcome = play_bin(
+        include_str!("levels/spawn.txt"),
+        env!("CARGO_BIN_EXE_episode3"),
+        3,
+    )?;
+    assert!(matches!(outcome, Outcome::Victory(5)), "{}", outcome);
+    Ok(())
+}